@@ -3,5 +3,5 @@ const EROSION_WORKGROUP_SIZE = 64;
 const EROSION_DISPATCH_SIZE = 16;
 const MAX_EROSION_STEPS = 500;
 const PI = 3.141592653589793;
-const HEIGHTMAP_IMAGE_SIZE = vec2<u32>(2064,2064);
 const BLUR_WORKGROUP_SIZE = 16;
+const THERMAL_WORKGROUP_SIZE = 16;
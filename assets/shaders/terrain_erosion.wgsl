@@ -5,6 +5,12 @@ const DROPLET_ARRAY_SIZE = constants::EROSION_WORKGROUP_SIZE * constants::EROSIO
 var<storage, read_write> droplets: array<Droplet, DROPLET_ARRAY_SIZE>;
 @group(0) @binding(1)
 var<storage, read_write> results: array<f32>;
+//The map size is part of the world settings, so it is set at runtime instead of being a constant
+@group(0) @binding(2)
+var<storage, read> image_size: vec2<u32>;
+//Tuning from the world settings, matches ErosionSettings in erosion.rs
+@group(0) @binding(3)
+var<uniform> settings: ErosionSettings;
 
 struct Droplet {
     position_x: u32,
@@ -17,13 +23,17 @@ struct Droplet {
     direction_y: f32,
 }
 
-const erosion_speed: f32 = 0.2;
-const gravity: f32 = 20.0;
-const deposition_speed: f32 = 0.2;
-const water_evaporation_speed: f32 = 0.0001;
-const minimum_slope: f32 = 0.01;
-const direction_inertia: f32 = 3.0;
-const carry_capacity_modifier: f32 = 1.0;
+struct ErosionSettings {
+    erosion_speed: f32,
+    gravity: f32,
+    deposition_speed: f32,
+    water_evaporation_speed: f32,
+    minimum_slope: f32,
+    direction_inertia: f32,
+    carry_capacity_modifier: f32,
+    min_droplet_size: u32,
+    max_droplet_size: u32,
+}
 
 @compute @workgroup_size(constants::EROSION_WORKGROUP_SIZE, 1, 1)
 fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
@@ -44,8 +54,8 @@ fn erosion(droplet_position: u32) {
                             continue;
                         }
                         var direction = vec2<f32>(position) - vec2<f32>(neighbour);
-                        let height_difference = results[position.x + position.y * constants::HEIGHTMAP_IMAGE_SIZE.x] - results[neighbour.x + neighbour.y * i32(constants::HEIGHTMAP_IMAGE_SIZE.x)];
-                        direction *= (-height_difference) * gravity * direction_inertia;
+                        let height_difference = results[position.x + position.y * image_size.x] - results[neighbour.x + neighbour.y * i32(image_size.x)];
+                        direction *= (-height_difference) * settings.gravity * settings.direction_inertia;
                         droplets[droplet_position].direction_x += direction.x;
                         droplets[droplet_position].direction_y += direction.y;
                     }
@@ -61,18 +71,18 @@ fn erosion(droplet_position: u32) {
             return;
         }
 
-        droplets[droplet_position].water *= 1.0 - water_evaporation_speed;
+        droplets[droplet_position].water *= 1.0 - settings.water_evaporation_speed;
 
-        let height_difference = results[position.x + position.y * constants::HEIGHTMAP_IMAGE_SIZE.x] - results[next_position.x + next_position.y * constants::HEIGHTMAP_IMAGE_SIZE.x];
-        droplets[droplet_position].speed += height_difference * gravity;
+        let height_difference = results[position.x + position.y * image_size.x] - results[next_position.x + next_position.y * image_size.x];
+        droplets[droplet_position].speed += height_difference * settings.gravity;
         droplets[droplet_position].direction_x *= droplets[droplet_position].speed;
         droplets[droplet_position].direction_y *= droplets[droplet_position].speed;
 
-        let carry_capacity = max(height_difference, minimum_slope) * droplets[droplet_position].speed * droplets[droplet_position].water * f32(droplets[droplet_position].radius) *  carry_capacity_modifier;
+        let carry_capacity = max(height_difference, settings.minimum_slope) * droplets[droplet_position].speed * droplets[droplet_position].water * f32(droplets[droplet_position].radius) *  settings.carry_capacity_modifier;
 
         if droplets[droplet_position].water < 0.1 || droplets[droplet_position].speed < 0.0 {
             droplets[droplet_position].radius *= u32(5);
-            deposit(droplet_position, droplets[droplet_position].sediment * deposition_speed);
+            deposit(droplet_position, droplets[droplet_position].sediment * settings.deposition_speed);
             return;
         }
         //else if height_difference < 0.0 {
@@ -83,11 +93,11 @@ fn erosion(droplet_position: u32) {
         //    droplets[droplet_position].radius = temp_radius;
         //}
         else if droplets[droplet_position].sediment > carry_capacity {
-            let deposit_amount = (droplets[droplet_position].sediment  - carry_capacity) * deposition_speed;
+            let deposit_amount = (droplets[droplet_position].sediment  - carry_capacity) * settings.deposition_speed;
             deposit(droplet_position, deposit_amount);
         }
         else {
-            let sediment_delta = (carry_capacity - droplets[droplet_position].sediment) * erosion_speed;
+            let sediment_delta = (carry_capacity - droplets[droplet_position].sediment) * settings.erosion_speed;
             let erosion_amount = min(sediment_delta, height_difference);
             erode(droplet_position, erosion_amount);
         }
@@ -95,7 +105,7 @@ fn erosion(droplet_position: u32) {
         droplets[droplet_position].position_y = next_position.y;
     }
     droplets[droplet_position].radius *= u32(5);
-    deposit(droplet_position, droplets[droplet_position].sediment * deposition_speed);
+    deposit(droplet_position, droplets[droplet_position].sediment * settings.deposition_speed);
     return;
 }
 
@@ -108,7 +118,7 @@ fn deposit(droplet_position: u32, amount: f32) {
                 if bound_check_i32(neighbour) && distance(vec2<f32>(position), vec2<f32>(neighbour)) < f32(radius){
                     let distance = distance(vec2<f32>(position), vec2<f32>(neighbour));
                     let deposit_amount = amount * normal_curve(0.0, f32(radius) * 0.5, f32(distance));
-                    results[neighbour.x + neighbour.y * i32(constants::HEIGHTMAP_IMAGE_SIZE.x)] = clamp(results[neighbour.x + neighbour.y * i32(constants::HEIGHTMAP_IMAGE_SIZE.x)] +  deposit_amount, 0.0, 1.0);
+                    results[neighbour.x + neighbour.y * i32(image_size.x)] = clamp(results[neighbour.x + neighbour.y * i32(image_size.x)] +  deposit_amount, 0.0, 1.0);
                 }
             }
         }
@@ -121,8 +131,8 @@ fn erode(droplet_position: u32, amount: f32) {
 
 fn blur_image(chunk_id: u32, total_chunks: u32, blur_amount: i32) {
     for (var b: i32 = 0; b <= blur_amount; b++) {
-        for (var x: i32 = 0; x < i32(constants::HEIGHTMAP_IMAGE_SIZE.x); x++) {
-            for (var y: i32 = 0; y < i32(constants::HEIGHTMAP_IMAGE_SIZE.y); y++) {
+        for (var x: i32 = 0; x < i32(image_size.x); x++) {
+            for (var y: i32 = 0; y < i32(image_size.y); y++) {
                 let radius = i32(1);
                 var sum = 0.0;
                 var length = 0;
@@ -130,12 +140,12 @@ fn blur_image(chunk_id: u32, total_chunks: u32, blur_amount: i32) {
                     for (var dy: i32 = -radius; dy <= radius; dy++) {
                     let neighbour = vec2<i32>(x + dx, y + dy);
                     if bound_check_i32(neighbour) {
-                            sum += results[neighbour.x + neighbour.y * i32(constants::HEIGHTMAP_IMAGE_SIZE.x)];
+                            sum += results[neighbour.x + neighbour.y * i32(image_size.x)];
                             length += 1;
                         }
                     }
                 }
-                results[x + y * i32(constants::HEIGHTMAP_IMAGE_SIZE.x)] = sum / f32(length);
+                results[x + y * i32(image_size.x)] = sum / f32(length);
             }
         }
     }
@@ -152,9 +162,9 @@ fn normal_curve(mean: f32, std_dev: f32, x: f32) -> f32 {
 }
 
 fn bound_check_u32(position: vec2<u32>) -> bool {
-    if position.x < constants::HEIGHTMAP_IMAGE_SIZE.x
+    if position.x < image_size.x
         && position.x > 0
-        && position.y < constants::HEIGHTMAP_IMAGE_SIZE.y
+        && position.y < image_size.y
         && position.y > 0
         {
             return true;
@@ -164,9 +174,9 @@ fn bound_check_u32(position: vec2<u32>) -> bool {
 }
 
 fn bound_check_i32(position: vec2<i32>) -> bool {
-    if position.x < i32(constants::HEIGHTMAP_IMAGE_SIZE.x)
+    if position.x < i32(image_size.x)
         && position.x > 0
-        && position.y < i32(constants::HEIGHTMAP_IMAGE_SIZE.y)
+        && position.y < i32(image_size.y)
         && position.y > 0
         {
             return true;
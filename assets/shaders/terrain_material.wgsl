@@ -1,28 +1,59 @@
 #import bevy_pbr::{
-    mesh_functions,
-    forward_io::{Vertex, VertexOutput},
-    view_transformations::position_world_to_clip,
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_bindings::{base_color_texture, base_color_sampler},
 }
 
-//@group(2) @binding(100)
-//var<uniform> heightmap_size: vec2f;
-@group(1) @binding(100)
-var heightmap: texture_2d;
-@group(1) @binding(101)
-var height_sampler: sampler;
+#ifdef PREPASS_PIPELINE
+#import bevy_pbr::{
+    prepass_io::{VertexOutput, FragmentOutput},
+    pbr_deferred_functions::deferred_output,
+}
+#else
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+}
+#endif
+
+@group(2) @binding(100)
+var<uniform> terrain_types: u32;
 
-@vertex
-fn vertex(vertex: Vertex) -> VertexOutput {
-    var out: VertexOutput;
-    var model = mesh_functions::get_model_matrix(vertex.instance_index);
-    out.world_normal = mesh_functions::mesh_normal_local_to_world(
-        vertex.normal,
-        vertex.instance_index
+//Each terrain type is a row of the atlas, and repeats once every tile
+fn terrain_colour(terrain_type: u32, tile_uv: vec2<f32>) -> vec4<f32> {
+    let row_scale = vec2<f32>(1.0, 1.0 / f32(terrain_types));
+    let uv = vec2<f32>(fract(tile_uv.x), f32(terrain_type) + fract(tile_uv.y)) * row_scale;
+    //The gradients come from the unwrapped uvs, so the mip level doesn't jump at the tile borders
+    return textureSampleGrad(
+        base_color_texture,
+        base_color_sampler,
+        uv,
+        dpdx(tile_uv) * row_scale,
+        dpdy(tile_uv) * row_scale
     );
-    let height = textureSampleLevel(heightmap, height_sampler, vertex.position.xz, 0.0);
-    //let height = 12.0;
-    out.world_position = mesh_functions::mesh_position_local_to_world(model, vec4<f32>(vertex.position.x, height, vertex.position.z, 1.0));
-    out.position = position_world_to_clip(out.world_position.xyz);
-    out.uv = vertex.uv;
+}
+
+@fragment
+fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+#ifdef VERTEX_COLORS
+    //Grass, dirt, stone and sand, snow is whatever is left over
+    let weights = in.color;
+    let snow = max(1.0 - weights.r - weights.g - weights.b - weights.a, 0.0);
+    let tile_uv = in.world_position.xz;
+    pbr_input.material.base_color = terrain_colour(0u, tile_uv) * weights.r
+        + terrain_colour(1u, tile_uv) * weights.g
+        + terrain_colour(2u, tile_uv) * weights.b
+        + terrain_colour(3u, tile_uv) * weights.a
+        + terrain_colour(4u, tile_uv) * snow;
+#endif
+
+#ifdef PREPASS_PIPELINE
+    let out = deferred_output(in, pbr_input);
+#else
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+#endif
     return out;
-}
\ No newline at end of file
+}
@@ -0,0 +1,68 @@
+#import constants
+
+@group(0) @binding(0)
+var<storage, read_write> heights: array<f32>;
+@group(0) @binding(1)
+var<storage, read_write> results: array<f32>;
+@group(0) @binding(2)
+var<storage, read> image_size: vec2<u32>;
+@group(0) @binding(3)
+var<storage, read> talus_height: f32;
+
+const THERMAL_RATE = 0.5;
+
+fn index(pos: vec2<i32>) -> i32 {
+    return pos.y * i32(image_size.x) + pos.x;
+}
+
+fn in_bounds(pos: vec2<i32>) -> bool {
+    let image_size = vec2<i32>(image_size);
+    return pos.x >= 0 && pos.x < image_size.x && pos.y >= 0 && pos.y < image_size.y;
+}
+
+//How far the drop to the neighbour goes past the talus angle, diagonal neighbours are further away
+fn excess(pos: vec2<i32>, neighbour: vec2<i32>) -> f32 {
+    let distance = length(vec2<f32>(neighbour - pos));
+    return max(heights[index(pos)] - heights[index(neighbour)] - talus_height * distance, 0.0);
+}
+
+//How much material slides off the point, and the total excess it is split by
+fn slide(pos: vec2<i32>) -> vec2<f32> {
+    var total = 0.0;
+    var steepest = 0.0;
+    for (var x: i32 = -1; x <= 1; x++) {
+        for (var y: i32 = -1; y <= 1; y++) {
+            let neighbour = pos + vec2<i32>(x, y);
+            if ((x == 0 && y == 0) || !in_bounds(neighbour)) {
+                continue;
+            }
+            let neighbour_excess = excess(pos, neighbour);
+            total += neighbour_excess;
+            steepest = max(steepest, neighbour_excess);
+        }
+    }
+    return vec2<f32>(steepest * THERMAL_RATE * 0.5, total);
+}
+
+//Gathers the material sliding in from the neighbours, so every point only writes to itself
+@compute @workgroup_size(constants::THERMAL_WORKGROUP_SIZE, constants::THERMAL_WORKGROUP_SIZE)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let pos = vec2<i32>(invocation_id.xy);
+    if (!in_bounds(pos)) {
+        return;
+    }
+    var result = heights[index(pos)] - slide(pos).x;
+    for (var x: i32 = -1; x <= 1; x++) {
+        for (var y: i32 = -1; y <= 1; y++) {
+            let neighbour = pos + vec2<i32>(x, y);
+            if ((x == 0 && y == 0) || !in_bounds(neighbour)) {
+                continue;
+            }
+            let neighbour_slide = slide(neighbour);
+            if (neighbour_slide.y > 0.0) {
+                result += neighbour_slide.x * excess(neighbour, pos) / neighbour_slide.y;
+            }
+        }
+    }
+    results[index(pos)] = result;
+}
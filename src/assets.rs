@@ -1,8 +1,9 @@
 use bevy::prelude::*;
 use enum_map::{Enum, EnumMap};
-use strum::IntoEnumIterator;
 use strum_macros::{Display, EnumIter};
 
+use crate::world_gen::terrain_material::ExtendedTerrainMaterial;
+
 pub mod asset_loader;
 
 #[derive(Resource, Default, Deref, DerefMut)]
@@ -12,17 +13,17 @@ pub struct TerrainTextures {
 
 #[derive(Resource, Default)]
 pub struct TerrainTextureAtlas {
-    pub handle: Handle<StandardMaterial>,
+    pub handle: Handle<ExtendedTerrainMaterial>,
 }
 
-pub fn get_terrain_texture_uv(terrain_type: TerrainType) -> [[f32; 2]; 4] {
-    let uv_height_start = (terrain_type as u32 as f32) / TerrainType::iter().len() as f32;
-    let uv_height_end = (terrain_type as u32 as f32 + 1.0) / TerrainType::iter().len() as f32;
-    let uv_0 = [0.0, uv_height_start];
-    let uv_1 = [1.0, uv_height_start];
-    let uv_2 = [1.0, uv_height_end];
-    let uv_3 = [0.0, uv_height_end];
-    [uv_0, uv_1, uv_2, uv_3]
+//Stored in the vertex colour, the terrain shader blends the atlas rows by it
+//Snow is last so it doesn't need a channel, it gets whatever weight the others leave over
+pub fn terrain_weights(terrain_type: TerrainType) -> [f32; 4] {
+    let mut weights = [0.0; 4];
+    if let Some(weight) = weights.get_mut(terrain_type as usize) {
+        *weight = 1.0;
+    }
+    weights
 }
 
 #[derive(Enum, EnumIter, Display, Copy, Clone, PartialEq, Eq, Hash, Debug)]
@@ -6,7 +6,10 @@ use image::{DynamicImage, RgbaImage};
 use itertools::Itertools;
 use strum::IntoEnumIterator;
 
-use crate::GameState;
+use crate::{
+    world_gen::terrain_material::{ExtendedTerrainMaterial, TerrainMaterial},
+    GameState,
+};
 
 use super::{TerrainTextureAtlas, TerrainTextures, TerrainType};
 
@@ -65,7 +68,7 @@ fn check_assets(
     mut image_assets: ResMut<Assets<Image>>,
     mut asset_load_bar: ResMut<AssetLoadBar>,
     mut terrain_texture_atlas: ResMut<TerrainTextureAtlas>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut materials: ResMut<Assets<ExtendedTerrainMaterial>>,
 ) {
     let mut progress = 0.0;
     for image in terrain_textures.values() {
@@ -92,12 +95,17 @@ fn check_assets(
         .unwrap();
         let image = DynamicImage::ImageRgba8(image);
         let image = Image::from_dynamic(image, false, RenderAssetUsages::RENDER_WORLD);
-        terrain_texture_atlas.handle = materials.add(StandardMaterial {
-            base_color_texture: Some(image_assets.add(image)),
-            alpha_mode: AlphaMode::Opaque,
-            specular_transmission: 0.0,
-            reflectance: 0.0,
-            ..Default::default()
+        terrain_texture_atlas.handle = materials.add(ExtendedTerrainMaterial {
+            base: StandardMaterial {
+                base_color_texture: Some(image_assets.add(image)),
+                alpha_mode: AlphaMode::Opaque,
+                specular_transmission: 0.0,
+                reflectance: 0.0,
+                ..Default::default()
+            },
+            extension: TerrainMaterial {
+                terrain_types: TerrainType::iter().len() as u32,
+            },
         });
 
         game_state.set(GameState::MainMenu);
@@ -1,6 +1,7 @@
 use bevy::{
     input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel},
     prelude::*,
+    window::PrimaryWindow,
 };
 use smooth_bevy_cameras::{
     controllers::orbit::{
@@ -10,16 +11,23 @@ use smooth_bevy_cameras::{
 };
 
 use crate::{
-    world::WorldEntity,
+    world::{WorldEntity, WorldSize},
     world_gen::{
-        consts::{CHUNK_SIZE, TILE_WORLD_SIZE, WORLD_HEIGHT_SCALE},
+        consts::{CHUNK_SIZE, TILE_SIZE, WORLD_HEIGHT_SCALE},
         heightmap::Heightmap,
         WorldSettings,
     },
     GameState, DEBUG,
 };
 
-#[derive(Reflect)]
+//How far apart the terrain is sampled along the cursor ray before narrowing down on the hit
+const RAY_MARCH_STEP: f32 = TILE_SIZE / 2.0;
+const RAY_REFINE_STEPS: u32 = 8;
+//Lifts the tile highlight off the ground so it isn't hidden in the terrain
+const HIGHLIGHT_OFFSET: f32 = 0.05;
+
+//Systems that read the terrain cursor run after this set
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CameraRaycastSet;
 
 pub struct CameraPlugin;
@@ -32,14 +40,49 @@ impl Plugin for CameraPlugin {
             },
             LookTransformPlugin,
         ));
+        app.init_resource::<TerrainCursor>();
         app.add_systems(OnEnter(GameState::World), setup);
         app.add_systems(Update, input.run_if(in_state(GameState::World)));
+        app.add_systems(
+            Update,
+            (
+                update_terrain_cursor.in_set(CameraRaycastSet),
+                highlight_hovered_tile.after(CameraRaycastSet),
+            )
+                .run_if(in_state(GameState::World)),
+        );
     }
 }
 
+//The camera the cursor is cast from
 #[derive(Component)]
 pub struct TerrainRaycaster;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TerrainHit {
+    //Where the cursor ray meets the terrain
+    pub position: Vec3,
+    //The tile the position is in, tile x covers x to x + 1
+    pub tile: [u32; 2],
+    pub chunk: [u32; 2],
+}
+
+impl TerrainHit {
+    //The heightmap point nearest the hit
+    pub fn point(&self) -> [u32; 2] {
+        [
+            self.position.x.round() as u32,
+            self.position.z.round() as u32,
+        ]
+    }
+}
+
+//What the cursor is over, None when it isn't over the terrain
+#[derive(Resource, Default, Debug)]
+pub struct TerrainCursor {
+    pub hovered: Option<TerrainHit>,
+}
+
 pub fn input(
     mut events: EventWriter<ControlEvent>,
     mut mouse_wheel_reader: EventReader<MouseWheel>,
@@ -132,9 +175,10 @@ pub fn input(
     }
 
     //Keep camera above terrain height
+    let tile_world_size = world_settings.tile_world_size();
 
-    if (transform.eye.x > 0.0 && transform.eye.x < TILE_WORLD_SIZE[0] as f32)
-        && (transform.eye.z > 0.0 && transform.eye.z < TILE_WORLD_SIZE[1] as f32)
+    if (transform.eye.x > 0.0 && transform.eye.x < tile_world_size[0] as f32)
+        && (transform.eye.z > 0.0 && transform.eye.z < tile_world_size[1] as f32)
     {
         let terrain_height = heightmap.interpolate_height(transform.eye.xz()) + 1.5;
         if transform.eye.y < terrain_height {
@@ -147,11 +191,11 @@ pub fn input(
     let clamp_factor = 0.01;
     transform.target.x = transform.target.x.clamp(
         CHUNK_SIZE as f32 * clamp_factor,
-        TILE_WORLD_SIZE[0] as f32 - (CHUNK_SIZE as f32 * clamp_factor),
+        tile_world_size[0] as f32 - (CHUNK_SIZE as f32 * clamp_factor),
     );
     transform.target.z = transform.target.z.clamp(
         CHUNK_SIZE as f32 * clamp_factor,
-        TILE_WORLD_SIZE[1] as f32 - (CHUNK_SIZE as f32 * clamp_factor),
+        tile_world_size[1] as f32 - (CHUNK_SIZE as f32 * clamp_factor),
     );
     transform.eye = transform.target + eye_delta;
 
@@ -201,6 +245,133 @@ fn setup(mut commands: Commands, heightmap: Res<Heightmap>) {
     );
     //Spawn Camera
     commands
-        .spawn((orbit_camera_bundle, WorldEntity))
+        .spawn((orbit_camera_bundle, WorldEntity, TerrainRaycaster))
         .insert(Camera3dBundle::default());
 }
+
+//Where the ray first goes under the terrain, marched along then narrowed down between the last two samples
+pub fn raycast_terrain(
+    heightmap: &Heightmap,
+    tile_world_size: WorldSize,
+    ray: Ray3d,
+) -> Option<Vec3> {
+    let on_map = |position: Vec3| {
+        position.x >= 0.0
+            && position.z >= 0.0
+            && position.x < tile_world_size[0] as f32
+            && position.z < tile_world_size[1] as f32
+    };
+    let below_ground = |position: Vec3| position.y <= heightmap.interpolate_height(position.xz());
+    let max_distance = Vec2::new(tile_world_size[0] as f32, tile_world_size[1] as f32).length()
+        + WORLD_HEIGHT_SCALE * 2.0;
+    let mut distance = 0.0;
+    let mut above = None;
+    while distance < max_distance {
+        let position = ray.get_point(distance);
+        if on_map(position) {
+            if below_ground(position) {
+                let (mut low, mut high) = (above.unwrap_or(distance), distance);
+                for _ in 0..RAY_REFINE_STEPS {
+                    let middle = (low + high) / 2.0;
+                    if below_ground(ray.get_point(middle)) {
+                        high = middle;
+                    } else {
+                        low = middle;
+                    }
+                }
+                return Some(ray.get_point(high));
+            }
+            above = Some(distance);
+        } else {
+            above = None;
+        }
+        distance += RAY_MARCH_STEP;
+    }
+    None
+}
+
+fn terrain_hit(position: Vec3, world_size: WorldSize) -> TerrainHit {
+    let tile = [position.x as u32, position.z as u32];
+    TerrainHit {
+        position,
+        tile,
+        chunk: [
+            (tile[0] / CHUNK_SIZE).min(world_size[0] - 1),
+            (tile[1] / CHUNK_SIZE).min(world_size[1] - 1),
+        ],
+    }
+}
+
+//The ray from the camera through the cursor, None when the cursor is outside the window
+fn cursor_ray(
+    cameras: &Query<(&Camera, &GlobalTransform), With<TerrainRaycaster>>,
+    windows: &Query<&Window, With<PrimaryWindow>>,
+) -> Option<Ray3d> {
+    let (camera, camera_transform) = cameras.iter().find(|c| c.0.is_active)?;
+    let cursor = windows.get_single().ok()?.cursor_position()?;
+    camera.viewport_to_world(camera_transform, cursor)
+}
+
+pub fn update_terrain_cursor(
+    mut terrain_cursor: ResMut<TerrainCursor>,
+    heightmap: Res<Heightmap>,
+    world_settings: Res<WorldSettings>,
+    cameras: Query<(&Camera, &GlobalTransform), With<TerrainRaycaster>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+) {
+    let hovered = cursor_ray(&cameras, &windows).and_then(|ray| {
+        let position = raycast_terrain(&heightmap, world_settings.tile_world_size(), ray)?;
+        Some(terrain_hit(position, world_settings.world_size()))
+    });
+    //Only written when it changes, so systems can use change detection to see the cursor move
+    if terrain_cursor.hovered != hovered {
+        terrain_cursor.hovered = hovered;
+    }
+}
+
+//Outlines the hovered tile along the terrain
+fn highlight_hovered_tile(
+    terrain_cursor: Res<TerrainCursor>,
+    heightmap: Res<Heightmap>,
+    mut gizmos: Gizmos,
+) {
+    let Some(hovered) = terrain_cursor.hovered else {
+        return;
+    };
+    let [x, y] = hovered.tile;
+    let corner = |point: [u32; 2]| {
+        Vec3::new(
+            point[0] as f32,
+            heightmap[point] * WORLD_HEIGHT_SCALE + HIGHLIGHT_OFFSET,
+            point[1] as f32,
+        )
+    };
+    gizmos.linestrip(
+        [[x, y], [x + 1, y], [x + 1, y + 1], [x, y + 1], [x, y]].map(corner),
+        Color::YELLOW,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rays_stop_at_the_first_ground_they_hit() {
+        let mut heightmap = Heightmap::new([1, 1]);
+        //A wall in front of the lower ground behind it
+        for y in 0..CHUNK_SIZE {
+            heightmap[[40, y]] = 0.1;
+        }
+        let down = Ray3d::new(Vec3::new(20.5, 100.0, 30.5), Vec3::NEG_Y);
+        let hit = raycast_terrain(&heightmap, [CHUNK_SIZE, CHUNK_SIZE], down).unwrap();
+        assert!(hit.distance(Vec3::new(20.5, 0.0, 30.5)) < 0.01);
+        assert_eq!(terrain_hit(hit, [1, 1]).tile, [20, 30]);
+        let across = Ray3d::new(Vec3::new(10.0, 20.0, 30.0), Vec3::X);
+        let hit = raycast_terrain(&heightmap, [CHUNK_SIZE, CHUNK_SIZE], across).unwrap();
+        assert!(hit.x > 39.0 && hit.x < 40.0);
+        //Off the map
+        let up = Ray3d::new(Vec3::new(20.5, 100.0, 30.5), Vec3::Y);
+        assert!(raycast_terrain(&heightmap, [CHUNK_SIZE, CHUNK_SIZE], up).is_none());
+    }
+}
@@ -0,0 +1,221 @@
+use std::{fs, path::PathBuf, str::FromStr, thread, time::Instant};
+
+use crate::{
+    save::{SaveFile, SaveFormat},
+    world_gen::{
+        erosion::cpu_erosion::erode_heightmap,
+        heightmap::{
+            export::{export_heightmap, ExportFormat},
+            import::import_heightmap,
+            Heightmap,
+        },
+        noise_gen::{continent_mask::ContinentShape, noise_function, NoiseFunction},
+        presets::{apply_preset, load_preset},
+        rivers::generate_rivers,
+        MapSize, WorldSettings,
+    },
+};
+
+const USAGE: &str = "Usage: city-builder generate [--preset <name>] [--seed <u32>] \
+[--hilliness <0.0-1.0>] [--mountains <u32>] [--mountain-size <f64>] [--erosion <u32>] \
+[--rivers <u32>] [--thermal <u32>] [--talus <degrees>] [--sea-level <u32>] \
+[--continent <none|island|peninsula|coast|landlocked>] \
+[--size <small|medium|large|WIDTHxHEIGHT>] [--layers <ron path>] \
+[--format <binary|quantised|ron>] [--import <png|tiff|r16|r32 path>] \
+[--export <png|r32|obj path>] [--out <path>]";
+
+//Runs the command line mode if the arguments ask for it, returns false if the game should start normally
+pub fn run(args: &[String]) -> bool {
+    match args.first().map(String::as_str) {
+        Some("generate") => {
+            if let Err(error) = generate(&args[1..]) {
+                eprintln!("{error}");
+                eprintln!("{USAGE}");
+                std::process::exit(1);
+            }
+            true
+        }
+        Some("--help" | "-h") => {
+            println!("{USAGE}");
+            true
+        }
+        _ => false,
+    }
+}
+
+struct GenerateArgs {
+    world_settings: WorldSettings,
+    format: SaveFormat,
+    import: Option<PathBuf>,
+    export: Option<(PathBuf, ExportFormat)>,
+    out: Option<PathBuf>,
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+    let value = value.ok_or(format!("Missing value for {flag}"))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {flag}: {value}"))
+}
+
+fn parse_map_size(value: Option<&String>) -> Result<MapSize, String> {
+    let value = value.ok_or("Missing value for --size")?;
+    match value.to_lowercase().as_str() {
+        "small" => Ok(MapSize::Small),
+        "medium" => Ok(MapSize::Medium),
+        "large" => Ok(MapSize::Large),
+        custom => {
+            let (x, y) = custom
+                .split_once('x')
+                .ok_or(format!("Invalid value for --size: {value}"))?;
+            let x = parse_value("--size", Some(&x.to_string()))?;
+            let y = parse_value("--size", Some(&y.to_string()))?;
+            Ok(MapSize::Custom([x, y]))
+        }
+    }
+}
+
+fn parse_generate_args(args: &[String]) -> Result<GenerateArgs, String> {
+    let mut world_settings = WorldSettings::default();
+    let mut format = SaveFormat::default();
+    let mut import = None;
+    let mut export = None;
+    let mut out = None;
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next();
+        match flag.as_str() {
+            //Replaces every setting but the seed and size, so other flags before it are lost
+            "--preset" => {
+                let name: String = parse_value(flag, value)?;
+                let preset = load_preset(&name).map_err(|error| error.to_string())?;
+                apply_preset(&mut world_settings, &preset);
+            }
+            "--seed" => world_settings.noise_settings.seed = parse_value(flag, value)?,
+            "--hilliness" => {
+                world_settings.noise_settings.hilliness =
+                    parse_value::<f64>(flag, value)?.clamp(0.0, 1.0);
+            }
+            "--mountains" => {
+                world_settings.noise_settings.mountain_amount = parse_value(flag, value)?;
+            }
+            "--mountain-size" => {
+                world_settings.noise_settings.mountain_size = parse_value(flag, value)?;
+            }
+            "--erosion" => world_settings.erosion_amount = parse_value(flag, value)?,
+            "--rivers" => world_settings.river_amount = parse_value(flag, value)?,
+            "--thermal" => world_settings.thermal_erosion.iterations = parse_value(flag, value)?,
+            "--talus" => {
+                world_settings.thermal_erosion.talus_angle =
+                    parse_value::<u32>(flag, value)?.min(89);
+            }
+            "--sea-level" => world_settings.water_level = parse_value(flag, value)?,
+            "--continent" => {
+                let name: String = parse_value(flag, value)?;
+                world_settings.noise_settings.continent_shape = ContinentShape::from_name(&name)
+                    .ok_or(format!("Invalid value for {flag}: {name}"))?;
+            }
+            "--size" => world_settings.map_size = parse_map_size(value)?,
+            "--layers" => {
+                let path = parse_value::<PathBuf>(flag, value)?;
+                let layers = fs::read_to_string(&path)
+                    .map_err(|error| format!("Couldn't read {}: {error}", path.display()))?;
+                world_settings.custom_noise_layers =
+                    Some(ron::from_str(&layers).map_err(|error| {
+                        format!("Invalid layers in {}: {error}", path.display())
+                    })?);
+            }
+            "--format" => {
+                let name: String = parse_value(flag, value)?;
+                format = SaveFormat::from_name(&name)
+                    .ok_or(format!("Invalid value for {flag}: {name}"))?;
+            }
+            "--import" => import = Some(parse_value::<PathBuf>(flag, value)?),
+            "--export" => {
+                let path = parse_value::<PathBuf>(flag, value)?;
+                let extension = path.extension().unwrap_or_default().to_string_lossy();
+                let format = ExportFormat::from_extension(&extension)
+                    .ok_or(format!("Can't export to .{extension} files"))?;
+                export = Some((path, format));
+            }
+            "--out" => out = Some(parse_value::<PathBuf>(flag, value)?),
+            _ => return Err(format!("Unknown argument: {flag}")),
+        }
+    }
+    if out.is_none() && export.is_none() {
+        return Err("Missing --out or --export".to_string());
+    }
+    let out = out.map(|out| out.with_extension("save"));
+    Ok(GenerateArgs {
+        world_settings,
+        format,
+        import,
+        export,
+        out,
+    })
+}
+
+//Same result as the world generation screen, but computed up front on plain threads without a window
+pub fn generate_heightmap(world_settings: &WorldSettings) -> Heightmap {
+    let world_size = world_settings.world_size();
+    let mut heightmap = Heightmap::new(world_size);
+    let column_size = heightmap.size()[1] as usize;
+    let threads = thread::available_parallelism().map_or(1, usize::from);
+    let columns_per_thread = (heightmap.size()[0] as usize).div_ceil(threads);
+    thread::scope(|s| {
+        for (thread_index, columns) in heightmap
+            .data
+            .chunks_mut(column_size * columns_per_thread)
+            .enumerate()
+        {
+            s.spawn(move || {
+                let noise = noise_function(world_settings);
+                for (column_index, column) in columns.chunks_mut(column_size).enumerate() {
+                    let x = (thread_index * columns_per_thread + column_index) as u32;
+                    for (y, height) in column.iter_mut().enumerate() {
+                        *height = noise.get([x, y as u32]) as f32;
+                    }
+                }
+            });
+        }
+    });
+    erode_heightmap(&mut heightmap, world_settings);
+    heightmap
+}
+
+fn generate(args: &[String]) -> Result<(), String> {
+    let GenerateArgs {
+        world_settings,
+        format,
+        import,
+        export,
+        out,
+    } = parse_generate_args(args)?;
+
+    let start_time = Instant::now();
+    let mut heightmap = match import {
+        //Imported heightmaps replace the noise, but are still eroded
+        Some(import) => {
+            let imported = import_heightmap(&import).map_err(|error| error.to_string())?;
+            let mut heightmap = imported.heightmap.resampled(world_settings.world_size());
+            erode_heightmap(&mut heightmap, &world_settings);
+            heightmap
+        }
+        None => generate_heightmap(&world_settings),
+    };
+    let rivers = generate_rivers(&mut heightmap, &world_settings);
+    println!("World generation took: {:?}", start_time.elapsed());
+
+    if let Some((path, export_format)) = export {
+        export_heightmap(&heightmap, &world_settings, &path, export_format)
+            .map_err(|error| error.to_string())?;
+        println!("Exported heightmap to {}", path.display());
+    }
+    if let Some(out) = out {
+        SaveFile::new(heightmap, world_settings, rivers)
+            .write(&out, format)
+            .map_err(|error| error.to_string())?;
+        println!("Saved world to {}", out.display());
+    }
+    Ok(())
+}
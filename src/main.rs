@@ -10,6 +10,7 @@
 mod assets;
 mod camera;
 mod debug;
+mod headless;
 mod menu;
 mod save;
 mod utils;
@@ -39,6 +40,11 @@ enum GameState {
 pub const DEBUG: bool = cfg!(debug_assertions);
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if headless::run(&args) {
+        return;
+    }
+
     create_shader_constants();
 
     let plugins = (
@@ -1,11 +1,12 @@
 use bevy::prelude::*;
 
 mod main_menu;
+mod save_browser;
 
 pub struct MenuPlugin;
 
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(main_menu::MainMenuPlugin);
+        app.add_plugins((main_menu::MainMenuPlugin, save_browser::SaveBrowserPlugin));
     }
 }
\ No newline at end of file
@@ -1,13 +1,12 @@
-use std::path::{Path, PathBuf};
-
 use bevy::prelude::*;
 
 use crate::{
-    save::{save_path, LoadEvent},
+    save::{LoadFailed, LoadFinished},
     GameState,
 };
 use bevy_egui::{egui, EguiContexts};
-use egui_file::FileDialog;
+
+use super::save_browser::SaveBrowser;
 
 pub struct MainMenuPlugin;
 
@@ -20,10 +19,38 @@ impl Plugin for MainMenuPlugin {
 fn main_menu(
     mut game_state: ResMut<NextState<GameState>>,
     mut contexts: EguiContexts,
-    mut file_dialog: Local<Option<FileDialog>>,
-    mut load_event: EventWriter<LoadEvent>,
+    mut save_browser: ResMut<SaveBrowser>,
+    mut load_finished: EventReader<LoadFinished>,
+    mut load_failed: EventReader<LoadFailed>,
+    mut load_error: Local<Option<String>>,
 ) {
+    //Only leave the menu once the save has actually been loaded
+    for finished in load_finished.read() {
+        println!("Loaded {}", finished.0.display());
+        game_state.set(GameState::World);
+    }
+    for failed in load_failed.read() {
+        *load_error = Some(format!("{}\n{}", failed.path.display(), failed.error));
+    }
+
     let ctx = contexts.ctx_mut();
+    if load_error.is_some() {
+        let mut open = true;
+        egui::Window::new("Failed to load save")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label(load_error.as_ref().unwrap());
+                if ui.button("Ok").clicked() {
+                    *load_error = None;
+                }
+            });
+        if !open {
+            *load_error = None;
+        }
+    }
     egui::CentralPanel::default().show(ctx, |ui| {
         ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
             let button = egui::Button::new("New Game").min_size([150.0, 65.0].into());
@@ -32,36 +59,7 @@ fn main_menu(
             }
             let button = egui::Button::new("Load Game").min_size([150.0, 65.0].into());
             if ui.add(button).clicked() {
-                if file_dialog.is_none() {
-                    let mut dialog = FileDialog::open_file(Some(save_path()))
-                        .show_new_folder(false)
-                        .show_rename(false)
-                        .show_files_filter(Box::new(|str: &Path| {
-                            str.extension().unwrap_or_default() == "save"
-                        }));
-                    #[cfg(windows)]
-                    {
-                        dialog = dialog.show_drives(false);
-                    }
-                    dialog.open();
-                    *file_dialog = Some(dialog);
-                }
-            }
-            if file_dialog.is_some() {
-                let dialog = file_dialog.as_mut().unwrap();
-                dialog.show(ctx);
-                let state = dialog.state();
-                match state {
-                    egui_file::State::Open => {}
-                    egui_file::State::Closed | egui_file::State::Cancelled => {
-                        *file_dialog = None;
-                    }
-                    egui_file::State::Selected => {
-                        let event = LoadEvent(PathBuf::from(dialog.path().unwrap()));
-                        load_event.send(event);
-                        game_state.set(GameState::World);
-                    }
-                }
+                save_browser.open();
             }
         });
     });
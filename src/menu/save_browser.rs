@@ -0,0 +1,299 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, TextureId},
+    EguiContexts,
+};
+
+use crate::{
+    save::{delete_save, duplicate_save, list_saves, rename_save, LoadEvent, SaveListing},
+    utils::math::AsF32,
+    GameState,
+};
+
+const THUMBNAIL_DISPLAY_SIZE: f32 = 96.0;
+
+pub struct SaveBrowserPlugin;
+
+impl Plugin for SaveBrowserPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SaveBrowser>();
+        app.add_systems(
+            Update,
+            (refresh_saves, save_browser)
+                .chain()
+                .run_if(in_state(GameState::MainMenu)),
+        );
+        app.add_systems(OnExit(GameState::MainMenu), close_save_browser);
+    }
+}
+
+struct SaveEntry {
+    listing: SaveListing,
+    thumbnail: Option<(Handle<Image>, TextureId)>,
+}
+
+#[derive(Resource, Default)]
+pub struct SaveBrowser {
+    open: bool,
+    //None until the save folder has been read
+    saves: Option<Vec<SaveEntry>>,
+    //Set whenever the save folder might have changed
+    stale: bool,
+    renaming: Option<(PathBuf, String)>,
+    confirm_delete: Option<PathBuf>,
+    error: Option<String>,
+}
+
+impl SaveBrowser {
+    pub fn open(&mut self) {
+        self.open = true;
+        self.refresh();
+    }
+    pub fn close(&mut self) {
+        self.open = false;
+        self.refresh();
+    }
+    fn refresh(&mut self) {
+        self.renaming = None;
+        self.confirm_delete = None;
+        self.error = None;
+        //The saves are reread in refresh_saves, since freeing the thumbnails needs the egui context
+        self.stale = true;
+    }
+}
+
+enum BrowserAction {
+    Load(PathBuf),
+    Delete(PathBuf),
+    Rename(PathBuf, String),
+    Duplicate(PathBuf),
+}
+
+fn free_thumbnails(
+    saves: &mut Vec<SaveEntry>,
+    contexts: &mut EguiContexts,
+    images: &mut Assets<Image>,
+) {
+    for (handle, _) in saves.iter_mut().filter_map(|save| save.thumbnail.take()) {
+        contexts.remove_image(&handle);
+        images.remove(&handle);
+    }
+}
+
+fn refresh_saves(
+    mut browser: ResMut<SaveBrowser>,
+    mut contexts: EguiContexts,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if !browser.stale {
+        return;
+    }
+    browser.stale = false;
+    if let Some(mut saves) = browser.saves.take() {
+        free_thumbnails(&mut saves, &mut contexts, &mut images);
+    }
+    if !browser.open {
+        return;
+    }
+
+    let listings = match list_saves() {
+        Ok(listings) => listings,
+        Err(error) => {
+            browser.error = Some(format!("Couldn't read the save folder: {error}"));
+            Vec::new()
+        }
+    };
+    let saves = listings
+        .into_iter()
+        .map(|listing| {
+            let thumbnail = listing.metadata.as_ref().ok().map(|metadata| {
+                let image = metadata
+                    .thumbnail
+                    .clone()
+                    .as_bevy_image(&metadata.world_gen_settings);
+                let handle = images.add(image);
+                let texture_id = contexts.add_image(handle.clone_weak());
+                (handle, texture_id)
+            });
+            SaveEntry { listing, thumbnail }
+        })
+        .collect();
+    browser.saves = Some(saves);
+}
+
+fn save_browser(
+    mut browser: ResMut<SaveBrowser>,
+    mut contexts: EguiContexts,
+    mut load_event: EventWriter<LoadEvent>,
+) {
+    if !browser.open {
+        return;
+    }
+    let browser = &mut *browser;
+    let Some(saves) = &browser.saves else {
+        return;
+    };
+
+    let mut action = None;
+    let mut open = true;
+    egui::Window::new("Load Game")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .open(&mut open)
+        .show(contexts.ctx_mut(), |ui| {
+            if let Some(error) = &browser.error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            if saves.is_empty() {
+                ui.label("There are no saves yet");
+            }
+            egui::ScrollArea::vertical()
+                .max_height(500.0)
+                .show(ui, |ui| {
+                    for save in saves {
+                        let file_name = &save.listing.file_name;
+                        ui.horizontal(|ui| {
+                            match save.thumbnail {
+                                Some((_, texture_id)) => {
+                                    let size = save
+                                        .listing
+                                        .metadata
+                                        .as_ref()
+                                        .unwrap()
+                                        .thumbnail
+                                        .size()
+                                        .as_f32();
+                                    let thumbnail = egui::Image::new(
+                                        egui::load::SizedTexture::new(texture_id, size),
+                                    )
+                                    .fit_to_exact_size(
+                                        [THUMBNAIL_DISPLAY_SIZE, THUMBNAIL_DISPLAY_SIZE].into(),
+                                    );
+                                    ui.add(thumbnail);
+                                }
+                                None => {
+                                    ui.add_sized(
+                                        [THUMBNAIL_DISPLAY_SIZE, THUMBNAIL_DISPLAY_SIZE],
+                                        egui::Label::new("?"),
+                                    );
+                                }
+                            }
+                            ui.vertical(|ui| {
+                                match &mut browser.renaming {
+                                    Some((renaming, new_name)) if renaming == file_name => {
+                                        let text_box = ui.text_edit_singleline(new_name);
+                                        if text_box.lost_focus()
+                                            && ui.input(|key| key.key_pressed(egui::Key::Enter))
+                                        {
+                                            action = Some(BrowserAction::Rename(
+                                                file_name.clone(),
+                                                new_name.clone(),
+                                            ));
+                                        }
+                                    }
+                                    _ => {
+                                        ui.strong(save.listing.name());
+                                    }
+                                }
+                                match &save.listing.metadata {
+                                    Ok(metadata) => {
+                                        let settings = &metadata.world_gen_settings;
+                                        let [x, y] = settings.world_size();
+                                        ui.label(format_date(metadata.saved_at));
+                                        ui.label(format!("Seed: {}", settings.noise_settings.seed));
+                                        ui.label(format!("Map Size: {x}x{y} chunks"));
+                                    }
+                                    Err(error) => {
+                                        ui.colored_label(egui::Color32::RED, error.to_string());
+                                    }
+                                }
+                                ui.horizontal(|ui| {
+                                    let loadable = save.listing.metadata.is_ok();
+                                    if ui
+                                        .add_enabled(loadable, egui::Button::new("Load"))
+                                        .clicked()
+                                    {
+                                        action = Some(BrowserAction::Load(file_name.clone()));
+                                    }
+                                    if ui.button("Rename").clicked() {
+                                        browser.renaming =
+                                            Some((file_name.clone(), save.listing.name()));
+                                    }
+                                    if ui.button("Duplicate").clicked() {
+                                        action = Some(BrowserAction::Duplicate(file_name.clone()));
+                                    }
+                                    if browser.confirm_delete.as_ref() == Some(file_name) {
+                                        if ui.button("Confirm Delete").clicked() {
+                                            action = Some(BrowserAction::Delete(file_name.clone()));
+                                        }
+                                        if ui.button("Cancel").clicked() {
+                                            browser.confirm_delete = None;
+                                        }
+                                    } else if ui.button("Delete").clicked() {
+                                        browser.confirm_delete = Some(file_name.clone());
+                                    }
+                                });
+                            });
+                        });
+                        ui.separator();
+                    }
+                });
+        });
+    if !open {
+        browser.close();
+        return;
+    }
+
+    let result = match action {
+        None => return,
+        Some(BrowserAction::Load(file_name)) => {
+            load_event.send(LoadEvent(file_name));
+            return;
+        }
+        Some(BrowserAction::Delete(file_name)) => delete_save(&file_name),
+        Some(BrowserAction::Rename(file_name, new_name)) => {
+            rename_save(&file_name, &new_name).map(|_| ())
+        }
+        Some(BrowserAction::Duplicate(file_name)) => duplicate_save(&file_name).map(|_| ()),
+    };
+    browser.refresh();
+    if let Err(error) = result {
+        browser.error = Some(error.to_string());
+    }
+}
+
+fn close_save_browser(mut browser: ResMut<SaveBrowser>) {
+    browser.close();
+}
+
+//Formats seconds since the unix epoch as a UTC date, without pulling in a date library
+fn format_date(saved_at: u64) -> String {
+    if saved_at == 0 {
+        return "Unknown date".to_string();
+    }
+    let days = (saved_at / 86400) as i64;
+    let minutes_today = saved_at % 86400 / 60;
+    //Converts days since the epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year}-{month:02}-{day:02} {:02}:{:02} UTC",
+        minutes_today / 60,
+        minutes_today % 60
+    )
+}
@@ -1,9 +1,25 @@
-use std::{env, fs, path::PathBuf};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::world_gen::{heightmap::Heightmap, WorldSettings};
+use crate::world_gen::{heightmap::Heightmap, rivers::RiverNetwork, WorldSettings};
+
+use self::binary::HeightEncoding;
+pub use self::error::{LoadError, SaveError};
+
+mod binary;
+mod error;
+mod migration;
+
+//Bump this and add a migration in save/migration.rs whenever the save format changes
+pub const SAVE_VERSION: u32 = 3;
+//Longest side of the thumbnail stored in the save metadata
+pub const THUMBNAIL_SIZE: u32 = 128;
 
 pub fn initalize_file_structure() {
     std::fs::create_dir_all(save_path()).unwrap();
@@ -15,16 +31,121 @@ pub fn save_path() -> PathBuf {
     path
 }
 
+//Ron is much larger and slower to load, so it is only meant for debugging
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SaveFormat {
+    #[default]
+    Binary,
+    //Smaller files, at the cost of rounding the heights to 16 bits
+    QuantisedBinary,
+    Ron,
+}
+
+impl SaveFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "binary" => Some(SaveFormat::Binary),
+            "quantised" => Some(SaveFormat::QuantisedBinary),
+            "ron" => Some(SaveFormat::Ron),
+            _ => None,
+        }
+    }
+    //The format can be changed with the CITY_BUILDER_SAVE_FORMAT environment variable
+    pub fn from_env() -> Self {
+        env::var("CITY_BUILDER_SAVE_FORMAT")
+            .ok()
+            .and_then(|name| Self::from_name(&name))
+            .unwrap_or_default()
+    }
+}
+
+//Everything the save browser shows, stored in front of the heightmap so it can be read on its own
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SaveMetadata {
+    //Seconds since the unix epoch, 0 for saves from before it was recorded
+    pub saved_at: u64,
+    pub world_gen_settings: WorldSettings,
+    pub thumbnail: Heightmap,
+}
+
+impl SaveMetadata {
+    pub fn new(heightmap: &Heightmap, world_gen_settings: WorldSettings) -> Self {
+        Self {
+            saved_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            world_gen_settings,
+            thumbnail: heightmap.downscale(THUMBNAIL_SIZE),
+        }
+    }
+    //Only binary saves of the current version can skip reading the heightmap
+    pub fn read(path: &Path) -> Result<Self, LoadError> {
+        match binary::read_metadata(&mut fs::File::open(path)?)? {
+            Some(metadata) => Ok(metadata),
+            None => Ok(SaveFile::read(path)?.metadata),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct SaveFile {
+    version: u32,
+    metadata: SaveMetadata,
     heightmap: Heightmap,
-    world_gen_settings: WorldSettings,
+    rivers: RiverNetwork,
+}
+
+impl SaveFile {
+    pub fn new(
+        heightmap: Heightmap,
+        world_gen_settings: WorldSettings,
+        rivers: RiverNetwork,
+    ) -> Self {
+        Self {
+            version: SAVE_VERSION,
+            metadata: SaveMetadata::new(&heightmap, world_gen_settings),
+            heightmap,
+            rivers,
+        }
+    }
+    pub fn write(&self, path: &Path, format: SaveFormat) -> Result<(), SaveError> {
+        let bytes = match format {
+            SaveFormat::Binary => binary::to_bytes(self, HeightEncoding::F32)?,
+            SaveFormat::QuantisedBinary => binary::to_bytes(self, HeightEncoding::QuantisedU16)?,
+            SaveFormat::Ron => ron::to_string(self)?.into_bytes(),
+        };
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+    //Reads a save in any format and from any version, upgrading it to the current version
+    pub fn read(path: &Path) -> Result<Self, LoadError> {
+        let bytes = fs::read(path)?;
+        if binary::is_binary(&bytes) {
+            binary::from_bytes(&bytes)
+        } else {
+            let text = std::str::from_utf8(&bytes)
+                .map_err(|_| LoadError::Corrupted("not a save file".to_string()))?;
+            migration::from_str(text)
+        }
+    }
 }
 
 #[derive(Event)]
 pub struct SaveEvent(pub PathBuf);
 #[derive(Event)]
 pub struct LoadEvent(pub PathBuf);
+#[derive(Event)]
+pub struct SaveFinished {
+    pub path: PathBuf,
+    pub result: Result<(), SaveError>,
+}
+#[derive(Event)]
+pub struct LoadFinished(pub PathBuf);
+#[derive(Event)]
+pub struct LoadFailed {
+    pub path: PathBuf,
+    pub error: LoadError,
+}
 
 pub struct SavePlugin;
 
@@ -32,6 +153,10 @@ impl Plugin for SavePlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<SaveEvent>();
         app.add_event::<LoadEvent>();
+        app.add_event::<SaveFinished>();
+        app.add_event::<LoadFinished>();
+        app.add_event::<LoadFailed>();
+        app.insert_resource(SaveFormat::from_env());
         app.add_systems(Startup, initalize_file_structure);
         app.add_systems(PostUpdate, (save_file, load_file));
     }
@@ -40,27 +165,140 @@ impl Plugin for SavePlugin {
 pub fn save_file(
     heightmap: Option<Res<Heightmap>>,
     world_gen_settings: Option<Res<WorldSettings>>,
+    rivers: Option<Res<RiverNetwork>>,
+    save_format: Res<SaveFormat>,
     mut save_event: EventReader<SaveEvent>,
+    mut save_finished: EventWriter<SaveFinished>,
 ) {
     for event in save_event.read() {
-        let heightmap = (*heightmap.as_ref().unwrap()).clone();
-        let world_gen_settings = (*world_gen_settings.as_ref().unwrap()).clone();
-
-        let save = SaveFile {
-            heightmap,
-            world_gen_settings,
+        let result = match (&heightmap, &world_gen_settings) {
+            (Some(heightmap), Some(world_gen_settings)) => {
+                //The rivers are only generated once erosion finishes
+                let rivers = rivers.as_deref().cloned().unwrap_or_default();
+                let save = SaveFile::new(
+                    (*heightmap).clone(),
+                    (*world_gen_settings).clone(),
+                    rivers,
+                );
+                save.write(&save_path().join(&event.0), *save_format)
+            }
+            _ => Err(SaveError::MissingWorld),
         };
-        let path = save_path().join(&event.0);
-        fs::write(path, &ron::to_string(&save).unwrap()).unwrap();
+        if let Err(error) = &result {
+            println!("Failed to save {}: {error}", event.0.display());
+        }
+        save_finished.send(SaveFinished {
+            path: event.0.clone(),
+            result,
+        });
     }
 }
 
-pub fn load_file(mut commands: Commands, mut load_event: EventReader<LoadEvent>) {
+pub fn load_file(
+    mut commands: Commands,
+    mut load_event: EventReader<LoadEvent>,
+    mut load_finished: EventWriter<LoadFinished>,
+    mut load_failed: EventWriter<LoadFailed>,
+) {
     for event in load_event.read() {
-        let path = save_path().join(&event.0);
-        let save: SaveFile = ron::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        match SaveFile::read(&save_path().join(&event.0)) {
+            Ok(save) => {
+                commands.insert_resource(save.heightmap);
+                commands.insert_resource(save.metadata.world_gen_settings);
+                commands.insert_resource(save.rivers);
+                load_finished.send(LoadFinished(event.0.clone()));
+            }
+            Err(error) => {
+                println!("Failed to load {}: {error}", event.0.display());
+                load_failed.send(LoadFailed {
+                    path: event.0.clone(),
+                    error,
+                });
+            }
+        }
+    }
+}
+
+pub struct SaveListing {
+    //Relative to the save folder
+    pub file_name: PathBuf,
+    pub metadata: Result<SaveMetadata, LoadError>,
+}
 
-        commands.insert_resource(save.heightmap.clone());
-        commands.insert_resource(save.world_gen_settings.clone());
+impl SaveListing {
+    pub fn name(&self) -> String {
+        self.file_name
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
     }
 }
+
+//Lists every save in the save folder, newest first. Broken saves are listed too so they can be deleted
+pub fn list_saves() -> io::Result<Vec<SaveListing>> {
+    let mut saves = Vec::new();
+    for entry in fs::read_dir(save_path())? {
+        let path = entry?.path();
+        if path.extension().unwrap_or_default() != "save" {
+            continue;
+        }
+        saves.push(SaveListing {
+            file_name: PathBuf::from(path.file_name().unwrap()),
+            metadata: SaveMetadata::read(&path),
+        });
+    }
+    saves.sort_by_key(|save| {
+        std::cmp::Reverse(
+            save.metadata
+                .as_ref()
+                .map_or(0, |metadata| metadata.saved_at),
+        )
+    });
+    Ok(saves)
+}
+
+pub fn delete_save(file_name: &Path) -> io::Result<()> {
+    fs::remove_file(save_path().join(file_name))
+}
+
+//Returns the new file name
+pub fn rename_save(file_name: &Path, new_name: &str) -> io::Result<PathBuf> {
+    //Appended rather than set, so a dot in the name isn't taken for an extension
+    let new_file_name = PathBuf::from(format!("{new_name}.save"));
+    if new_name.is_empty() || new_file_name.components().count() != 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("\"{new_name}\" is not a valid save name"),
+        ));
+    }
+    if new_file_name == file_name {
+        return Ok(new_file_name);
+    }
+    let new_path = save_path().join(&new_file_name);
+    if new_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("A save called \"{new_name}\" already exists"),
+        ));
+    }
+    fs::rename(save_path().join(file_name), new_path)?;
+    Ok(new_file_name)
+}
+
+//Copies the save to the first free "<name> copy", "<name> copy 2", ... and returns the new file name
+pub fn duplicate_save(file_name: &Path) -> io::Result<PathBuf> {
+    let name = file_name.file_stem().unwrap_or_default().to_string_lossy();
+    let new_file_name = (1..)
+        .map(|copy| match copy {
+            1 => PathBuf::from(format!("{name} copy.save")),
+            _ => PathBuf::from(format!("{name} copy {copy}.save")),
+        })
+        .find(|new_file_name| !save_path().join(new_file_name).exists())
+        .unwrap();
+    fs::copy(
+        save_path().join(file_name),
+        save_path().join(&new_file_name),
+    )?;
+    Ok(new_file_name)
+}
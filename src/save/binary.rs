@@ -0,0 +1,325 @@
+use std::io::Read;
+
+use crate::world_gen::{heightmap::Heightmap, rivers::RiverNetwork};
+
+use super::{migration, LoadError, SaveError, SaveFile, SaveMetadata, SAVE_VERSION};
+
+//Layout, all numbers little endian:
+// magic, save version: u32, header length: u32, header as ron,
+// rivers length: u32, lz4 compressed rivers as ron with the uncompressed size prepended,
+// heightmap width: u32, heightmap height: u32, height encoding: u8,
+// lz4 compressed heights with the uncompressed size prepended
+//The header is the save metadata, binary saves were added after it in version 2
+//The rivers were added in version 3
+const MAGIC: &[u8; 8] = b"CITYSAVE";
+//LZ4 can't shrink data by more than this, so a bigger prepended size means the file is corrupted
+const MAX_LZ4_RATIO: usize = 255;
+//The header is only the settings and a small thumbnail, so a longer length means the file is corrupted
+const MAX_HEADER_LENGTH: usize = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeightEncoding {
+    F32 = 0,
+    //Heights are between 0 and 1, so they are stored as fractions of u16::MAX
+    QuantisedU16 = 1,
+}
+
+impl HeightEncoding {
+    fn from_u8(value: u8) -> Result<Self, LoadError> {
+        match value {
+            0 => Ok(HeightEncoding::F32),
+            1 => Ok(HeightEncoding::QuantisedU16),
+            _ => Err(LoadError::Corrupted(format!(
+                "unknown height encoding {value}"
+            ))),
+        }
+    }
+    fn bytes_per_height(self) -> usize {
+        match self {
+            HeightEncoding::F32 => 4,
+            HeightEncoding::QuantisedU16 => 2,
+        }
+    }
+}
+
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn to_bytes(save: &SaveFile, encoding: HeightEncoding) -> Result<Vec<u8>, SaveError> {
+    let header = ron::to_string(&save.metadata)?;
+    let rivers = lz4_flex::compress_prepend_size(ron::to_string(&save.rivers)?.as_bytes());
+    let [width, height] = save.heightmap.size();
+    let heights: Vec<u8> = match encoding {
+        HeightEncoding::F32 => save
+            .heightmap
+            .data
+            .iter()
+            .flat_map(|height| height.to_le_bytes())
+            .collect(),
+        HeightEncoding::QuantisedU16 => save
+            .heightmap
+            .data
+            .iter()
+            .flat_map(|height| {
+                ((height.clamp(0.0, 1.0) * f32::from(u16::MAX)).round() as u16).to_le_bytes()
+            })
+            .collect(),
+    };
+
+    let mut bytes = Vec::new();
+    bytes.extend(MAGIC);
+    bytes.extend(save.version.to_le_bytes());
+    bytes.extend((header.len() as u32).to_le_bytes());
+    bytes.extend(header.as_bytes());
+    bytes.extend((rivers.len() as u32).to_le_bytes());
+    bytes.extend(rivers);
+    bytes.extend(width.to_le_bytes());
+    bytes.extend(height.to_le_bytes());
+    bytes.push(encoding as u8);
+    bytes.extend(lz4_flex::compress_prepend_size(&heights));
+    Ok(bytes)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], LoadError> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + length)
+            .ok_or(LoadError::Corrupted("file is truncated".to_string()))?;
+        self.position += length;
+        Ok(bytes)
+    }
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn rest(&mut self) -> &'a [u8] {
+        &self.bytes[self.position..]
+    }
+}
+
+//Checks the prepended size before decompressing, so a corrupted size can't make it allocate too much
+fn decompress(bytes: &[u8], expected: Option<usize>) -> Result<Vec<u8>, LoadError> {
+    let size = bytes
+        .get(..4)
+        .map(|size| u32::from_le_bytes(size.try_into().unwrap()) as usize)
+        .ok_or(LoadError::Corrupted("file is truncated".to_string()))?;
+    if size > bytes.len().saturating_mul(MAX_LZ4_RATIO)
+        || expected.is_some_and(|expected| size != expected)
+    {
+        return Err(LoadError::Corrupted(format!(
+            "compressed data has the wrong size {size}"
+        )));
+    }
+    lz4_flex::decompress_size_prepended(bytes)
+        .map_err(|error| LoadError::Corrupted(error.to_string()))
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Result<SaveFile, LoadError> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(LoadError::Corrupted("not a binary save file".to_string()));
+    }
+    let version = reader.u32()?;
+    let header_length = reader.u32()? as usize;
+    let header = std::str::from_utf8(reader.take(header_length)?)
+        .map_err(|error| LoadError::Corrupted(error.to_string()))?;
+    let rivers = if version >= 3 {
+        let rivers_length = reader.u32()? as usize;
+        let rivers = decompress(reader.take(rivers_length)?, None)?;
+        let rivers = std::str::from_utf8(&rivers)
+            .map_err(|error| LoadError::Corrupted(error.to_string()))?;
+        ron::from_str(rivers)?
+    } else {
+        RiverNetwork::default()
+    };
+    let size = [reader.u32()?, reader.u32()?];
+    let encoding = HeightEncoding::from_u8(reader.u8()?)?;
+    let length = migration::heightmap_length(size)?;
+    let heights_length = length
+        .checked_mul(encoding.bytes_per_height())
+        .ok_or_else(|| LoadError::Corrupted("heightmap size is too big".to_string()))?;
+    let heights = decompress(reader.rest(), Some(heights_length))?;
+
+    let data = match encoding {
+        HeightEncoding::F32 => heights
+            .chunks_exact(4)
+            .map(|height| f32::from_le_bytes(height.try_into().unwrap()))
+            .collect(),
+        HeightEncoding::QuantisedU16 => heights
+            .chunks_exact(2)
+            .map(|height| {
+                f32::from(u16::from_le_bytes(height.try_into().unwrap())) / f32::from(u16::MAX)
+            })
+            .collect::<Vec<f32>>(),
+    };
+    if data.len() != length {
+        return Err(LoadError::Corrupted(format!(
+            "heightmap has {} heights, expected {}x{}",
+            data.len(),
+            size[0],
+            size[1]
+        )));
+    }
+
+    migration::from_parts(version, Heightmap::from_raw(size, data), header, rivers)
+}
+
+//Reads just the metadata header, returns None if the save isn't a binary save of the current version
+pub fn read_metadata(file: &mut impl Read) -> Result<Option<SaveMetadata>, LoadError> {
+    //Magic, version and header length
+    let mut start = Vec::new();
+    file.by_ref()
+        .take(MAGIC.len() as u64 + 8)
+        .read_to_end(&mut start)?;
+    if start.len() < MAGIC.len() + 8 || !is_binary(&start) {
+        return Ok(None);
+    }
+    let mut reader = Reader {
+        bytes: &start,
+        position: MAGIC.len(),
+    };
+    if reader.u32()? != SAVE_VERSION {
+        return Ok(None);
+    }
+    //Checked before allocating, the save browser reads the header of every save
+    let header_length = reader.u32()? as usize;
+    if header_length > MAX_HEADER_LENGTH {
+        return Err(LoadError::Corrupted(format!(
+            "header has the wrong length {header_length}"
+        )));
+    }
+    let mut header = vec![0; header_length];
+    file.read_exact(&mut header)
+        .map_err(|_| LoadError::Corrupted("file is truncated".to_string()))?;
+    let header =
+        std::str::from_utf8(&header).map_err(|error| LoadError::Corrupted(error.to_string()))?;
+    let metadata: SaveMetadata = ron::from_str(header)?;
+    migration::validate_heightmap(&metadata.thumbnail)?;
+    Ok(Some(metadata))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::world_gen::{
+        rivers::{River, RiverPoint},
+        MapSize, WorldSettings,
+    };
+
+    use super::*;
+
+    fn test_save() -> SaveFile {
+        let mut heightmap = Heightmap::new([1, 1]);
+        for (index, height) in heightmap.data.iter_mut().enumerate() {
+            *height = (index % 100) as f32 / 100.0;
+        }
+        let settings = WorldSettings {
+            map_size: MapSize::Custom([1, 1]),
+            ..Default::default()
+        };
+        let rivers = RiverNetwork {
+            rivers: vec![River {
+                points: vec![RiverPoint {
+                    position: [3, 4],
+                    flow: 1.0,
+                }],
+            }],
+            lakes: Vec::new(),
+        };
+        SaveFile::new(heightmap, settings, rivers)
+    }
+
+    #[test]
+    fn f32_encoding_is_lossless() {
+        let save = test_save();
+        let bytes = to_bytes(&save, HeightEncoding::F32).unwrap();
+        assert!(is_binary(&bytes));
+        let loaded = from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.heightmap.data, save.heightmap.data);
+        assert_eq!(loaded.heightmap.size(), save.heightmap.size());
+        assert!(loaded.metadata.world_gen_settings == save.metadata.world_gen_settings);
+        assert_eq!(loaded.rivers, save.rivers);
+    }
+
+    #[test]
+    fn metadata_is_read_without_the_heightmap() {
+        let save = test_save();
+        let bytes = to_bytes(&save, HeightEncoding::F32).unwrap();
+        let metadata = read_metadata(&mut bytes.as_slice()).unwrap().unwrap();
+        assert_eq!(metadata.saved_at, save.metadata.saved_at);
+        assert_eq!(metadata.thumbnail.data, save.metadata.thumbnail.data);
+        let ron = ron::to_string(&save).unwrap();
+        assert!(read_metadata(&mut ron.as_bytes()).unwrap().is_none());
+    }
+
+    #[test]
+    fn quantised_encoding_is_close() {
+        let save = test_save();
+        let loaded = from_bytes(&to_bytes(&save, HeightEncoding::QuantisedU16).unwrap()).unwrap();
+        for (loaded, original) in loaded.heightmap.data.iter().zip(&save.heightmap.data) {
+            assert!((loaded - original).abs() <= 1.0 / f32::from(u16::MAX));
+        }
+    }
+
+    #[test]
+    fn truncated_saves_are_rejected() {
+        let bytes = to_bytes(&test_save(), HeightEncoding::F32).unwrap();
+        let truncated = &bytes[..bytes.len() / 2];
+        assert!(matches!(
+            from_bytes(truncated),
+            Err(LoadError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn corrupted_sizes_are_rejected() {
+        let save = test_save();
+        let bytes = to_bytes(&save, HeightEncoding::F32).unwrap();
+        //The heightmap width, height, encoding and the prepended size of the heights come last
+        let heights_start = bytes.len()
+            - lz4_flex::compress_prepend_size(
+                &save
+                    .heightmap
+                    .data
+                    .iter()
+                    .flat_map(|height| height.to_le_bytes())
+                    .collect::<Vec<_>>(),
+            )
+            .len();
+        let size_start = heights_start - 9;
+        //A size whose heights overflow a u32
+        let mut overflowing = bytes.clone();
+        overflowing[size_start..size_start + 8].copy_from_slice(&[0xFF; 8]);
+        assert!(matches!(
+            from_bytes(&overflowing),
+            Err(LoadError::Corrupted(_))
+        ));
+        //A prepended size far bigger than the data could hold
+        let mut oversized = bytes.clone();
+        oversized[heights_start..heights_start + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            from_bytes(&oversized),
+            Err(LoadError::Corrupted(_))
+        ));
+    }
+
+    #[test]
+    fn oversized_headers_are_rejected() {
+        let mut bytes = to_bytes(&test_save(), HeightEncoding::F32).unwrap();
+        let length_start = MAGIC.len() + 4;
+        bytes[length_start..length_start + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            read_metadata(&mut bytes.as_slice()),
+            Err(LoadError::Corrupted(_))
+        ));
+        assert!(matches!(from_bytes(&bytes), Err(LoadError::Corrupted(_))));
+    }
+}
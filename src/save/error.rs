@@ -0,0 +1,72 @@
+use std::{fmt, io};
+
+use super::SAVE_VERSION;
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Serialize(ron::Error),
+    //Saving was requested before a world was generated or loaded
+    MissingWorld,
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(error) => write!(f, "Couldn't write the save file: {error}"),
+            SaveError::Serialize(error) => write!(f, "Couldn't serialize the world: {error}"),
+            SaveError::MissingWorld => write!(f, "There is no world to save"),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(error: io::Error) -> Self {
+        SaveError::Io(error)
+    }
+}
+
+impl From<ron::Error> for SaveError {
+    fn from(error: ron::Error) -> Self {
+        SaveError::Serialize(error)
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+    UnsupportedVersion(u32),
+    //The file parsed, but its contents don't make sense
+    Corrupted(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "Couldn't read the save file: {error}"),
+            LoadError::Parse(error) => write!(f, "The save file is invalid: {error}"),
+            LoadError::UnsupportedVersion(version) => write!(
+                f,
+                "Save version {version} is newer than the supported version {SAVE_VERSION}"
+            ),
+            LoadError::Corrupted(reason) => write!(f, "The save file is corrupted: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(error: io::Error) -> Self {
+        LoadError::Io(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for LoadError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        LoadError::Parse(error)
+    }
+}
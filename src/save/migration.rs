@@ -0,0 +1,413 @@
+use serde::Deserialize;
+
+use crate::{
+    world::WorldSize,
+    world_gen::{
+        consts::HEIGHTMAP_CHUNK_SIZE,
+        erosion::{thermal_erosion::ThermalErosionSettings, ErosionSettings},
+        heightmap::Heightmap,
+        noise_gen::NoiseSettings,
+        rivers::RiverNetwork,
+        MapSize, WorldSettings,
+    },
+};
+
+use super::{LoadError, SaveFile, SaveMetadata};
+
+//Only reads the version, saves from before versioning have no version field
+#[derive(Deserialize)]
+struct SaveHeader {
+    #[serde(default)]
+    version: u32,
+}
+
+//Version 0: unversioned saves, always medium sized maps
+#[derive(Deserialize)]
+struct SaveFileV0 {
+    heightmap: Heightmap,
+    world_gen_settings: WorldSettingsV0,
+}
+
+#[derive(Deserialize)]
+struct WorldSettingsV0 {
+    noise_settings: NoiseSettings,
+    water_level: u32,
+    erosion_amount: u32,
+}
+
+impl From<SaveFileV0> for SaveFileV1 {
+    fn from(save: SaveFileV0) -> Self {
+        let settings = save.world_gen_settings;
+        SaveFileV1 {
+            heightmap: save.heightmap,
+            world_gen_settings: WorldSettings {
+                noise_settings: settings.noise_settings,
+                water_level: settings.water_level,
+                erosion_amount: settings.erosion_amount,
+                erosion: ErosionSettings::default(),
+                map_size: MapSize::Medium,
+                river_amount: 0,
+                thermal_erosion: ThermalErosionSettings::default(),
+                custom_noise_layers: None,
+            },
+        }
+    }
+}
+
+//Version 1: no metadata header
+#[derive(Deserialize)]
+struct SaveFileV1 {
+    heightmap: Heightmap,
+    world_gen_settings: WorldSettings,
+}
+
+impl From<SaveFileV1> for SaveFileV2 {
+    fn from(save: SaveFileV1) -> Self {
+        SaveFileV2 {
+            metadata: SaveMetadata {
+                saved_at: 0,
+                ..SaveMetadata::new(&save.heightmap, save.world_gen_settings)
+            },
+            heightmap: save.heightmap,
+        }
+    }
+}
+
+//Version 2: no rivers
+#[derive(Deserialize)]
+struct SaveFileV2 {
+    metadata: SaveMetadata,
+    heightmap: Heightmap,
+}
+
+impl From<SaveFileV2> for SaveFile {
+    fn from(save: SaveFileV2) -> Self {
+        SaveFile {
+            version: 3,
+            metadata: save.metadata,
+            heightmap: save.heightmap,
+            rivers: RiverNetwork::default(),
+        }
+    }
+}
+
+enum VersionedSave {
+    V0(SaveFileV0),
+    V1(SaveFileV1),
+    V2(SaveFileV2),
+    V3(SaveFile),
+}
+
+impl VersionedSave {
+    fn parse(version: u32, text: &str) -> Result<Self, LoadError> {
+        Ok(match version {
+            0 => VersionedSave::V0(ron::from_str(text)?),
+            1 => VersionedSave::V1(ron::from_str(text)?),
+            2 => VersionedSave::V2(ron::from_str(text)?),
+            3 => VersionedSave::V3(ron::from_str(text)?),
+            _ => return Err(LoadError::UnsupportedVersion(version)),
+        })
+    }
+    //Binary saves store the heightmap separately from the rest of the save, which is kept as ron
+    //They were added after the metadata header, and the rivers are empty for versions from before they were saved
+    fn from_parts(
+        version: u32,
+        heightmap: Heightmap,
+        header: &str,
+        rivers: RiverNetwork,
+    ) -> Result<Self, LoadError> {
+        Ok(match version {
+            2 => VersionedSave::V2(SaveFileV2 {
+                metadata: ron::from_str(header)?,
+                heightmap,
+            }),
+            3 => VersionedSave::V3(SaveFile {
+                version,
+                metadata: ron::from_str(header)?,
+                heightmap,
+                rivers,
+            }),
+            _ => return Err(LoadError::UnsupportedVersion(version)),
+        })
+    }
+    //Upgrades one version at a time until the save is in the current format
+    fn upgrade(self) -> SaveFile {
+        match self {
+            VersionedSave::V0(save) => VersionedSave::V1(save.into()).upgrade(),
+            VersionedSave::V1(save) => VersionedSave::V2(save.into()).upgrade(),
+            VersionedSave::V2(save) => VersionedSave::V3(save.into()).upgrade(),
+            VersionedSave::V3(save) => save,
+        }
+    }
+    //Checked before upgrading, since upgrading reads the heightmap
+    fn validate(&self) -> Result<(), LoadError> {
+        match self {
+            VersionedSave::V0(save) => validate_heightmap(&save.heightmap),
+            VersionedSave::V1(save) => validate_heightmap(&save.heightmap),
+            VersionedSave::V2(SaveFileV2 {
+                heightmap,
+                metadata,
+            })
+            | VersionedSave::V3(SaveFile {
+                heightmap,
+                metadata,
+                ..
+            }) => {
+                validate_heightmap(heightmap)?;
+                validate_heightmap(&metadata.thumbnail)
+            }
+        }
+    }
+}
+
+//How many heights a heightmap of the size has, the size comes from the file so it can be anything
+pub fn heightmap_length([width, height]: WorldSize) -> Result<usize, LoadError> {
+    (width as usize)
+        .checked_mul(height as usize)
+        .ok_or_else(|| LoadError::Corrupted(format!("heightmap size {width}x{height} is too big")))
+}
+
+//Ron saves can contain a heightmap whose data doesn't match its size
+pub fn validate_heightmap(heightmap: &Heightmap) -> Result<(), LoadError> {
+    let [width, height] = heightmap.size();
+    if heightmap.data.len() == heightmap_length(heightmap.size())? {
+        Ok(())
+    } else {
+        Err(LoadError::Corrupted(format!(
+            "heightmap has {} heights, expected {width}x{height}",
+            heightmap.data.len()
+        )))
+    }
+}
+
+//The world is remade from the map size, so a heightmap of any other size can't be played
+fn validate_map_size(save: &SaveFile) -> Result<(), LoadError> {
+    let [width, height] = save.heightmap.size();
+    let [chunks_x, chunks_y] = save.metadata.world_gen_settings.world_size();
+    if [width, height]
+        == [
+            chunks_x * HEIGHTMAP_CHUNK_SIZE,
+            chunks_y * HEIGHTMAP_CHUNK_SIZE,
+        ]
+    {
+        Ok(())
+    } else {
+        Err(LoadError::Corrupted(format!(
+            "heightmap is {width}x{height}, expected {chunks_x}x{chunks_y} chunks"
+        )))
+    }
+}
+
+fn upgrade(save: VersionedSave) -> Result<SaveFile, LoadError> {
+    save.validate()?;
+    let save = save.upgrade();
+    validate_map_size(&save)?;
+    Ok(save)
+}
+
+pub fn from_str(text: &str) -> Result<SaveFile, LoadError> {
+    let header: SaveHeader = ron::from_str(text)?;
+    upgrade(VersionedSave::parse(header.version, text)?)
+}
+
+pub fn from_parts(
+    version: u32,
+    heightmap: Heightmap,
+    header: &str,
+    rivers: RiverNetwork,
+) -> Result<SaveFile, LoadError> {
+    upgrade(VersionedSave::from_parts(
+        version, heightmap, header, rivers,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use serde::Serialize;
+
+    use crate::{
+        save::{SAVE_VERSION, THUMBNAIL_SIZE},
+        world_gen::{noise_gen::layers::NoiseLayer, rivers::Lake},
+    };
+
+    use super::*;
+
+    fn corpus_path() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("saves")
+    }
+
+    //Unversioned saves have a medium heightmap, too big to keep in the corpus, so the test writes one instead
+    #[test]
+    fn every_version_has_a_save_in_the_corpus() {
+        for version in 1..=SAVE_VERSION {
+            let path = corpus_path().join(format!("v{version}.save"));
+            assert!(path.exists(), "Missing save for version {version}");
+        }
+    }
+
+    #[test]
+    fn corpus_saves_load_as_current_version() {
+        for entry in fs::read_dir(corpus_path()).unwrap() {
+            let path = entry.unwrap().path();
+            let save = SaveFile::read(&path).unwrap();
+            assert_eq!(save.version, SAVE_VERSION, "{}", path.display());
+            assert_eq!(
+                save.heightmap.data.len(),
+                heightmap_length(save.heightmap.size()).unwrap(),
+                "{}",
+                path.display()
+            );
+            //The corpus saves are playable, not just readable
+            let [chunks_x, chunks_y] = save.metadata.world_gen_settings.world_size();
+            assert_eq!(
+                save.heightmap.size(),
+                [
+                    chunks_x * HEIGHTMAP_CHUNK_SIZE,
+                    chunks_y * HEIGHTMAP_CHUNK_SIZE
+                ],
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn unversioned_saves_become_medium_maps() {
+        //Written the way saves were before they had a version
+        #[derive(Serialize)]
+        struct UnversionedSave {
+            heightmap: UnversionedHeightmap,
+            world_gen_settings: UnversionedSettings,
+        }
+        #[derive(Serialize)]
+        struct UnversionedHeightmap {
+            data: Vec<f32>,
+            size: WorldSize,
+        }
+        #[derive(Serialize)]
+        struct UnversionedSettings {
+            noise_settings: UnversionedNoiseSettings,
+            water_level: u32,
+            erosion_amount: u32,
+        }
+        #[derive(Serialize)]
+        struct UnversionedNoiseSettings {
+            seed: u32,
+            mountain_amount: u32,
+            mountain_size: f64,
+            hilliness: f64,
+        }
+
+        let [chunks_x, chunks_y] = MapSize::Medium.chunks();
+        let size = [
+            chunks_x * HEIGHTMAP_CHUNK_SIZE,
+            chunks_y * HEIGHTMAP_CHUNK_SIZE,
+        ];
+        let data: Vec<f32> = (0..heightmap_length(size).unwrap())
+            .map(|index| (index % 4) as f32 * 0.25)
+            .collect();
+        let text = ron::to_string(&UnversionedSave {
+            heightmap: UnversionedHeightmap {
+                data: data.clone(),
+                size,
+            },
+            world_gen_settings: UnversionedSettings {
+                noise_settings: UnversionedNoiseSettings {
+                    seed: 42,
+                    mountain_amount: 3,
+                    mountain_size: 0.5,
+                    hilliness: 0.25,
+                },
+                water_level: 12,
+                erosion_amount: 4,
+            },
+        })
+        .unwrap();
+        assert!(!text.contains("version"));
+
+        let save = from_str(&text).unwrap();
+        assert_eq!(save.version, SAVE_VERSION);
+        assert_eq!(save.heightmap.size(), size);
+        assert_eq!(save.heightmap.data, data);
+        let settings = &save.metadata.world_gen_settings;
+        assert_eq!(settings.map_size, MapSize::Medium);
+        assert_eq!(settings.noise_settings.seed, 42);
+        assert_eq!(settings.noise_settings.mountain_amount, 3);
+        assert_eq!(settings.water_level, 12);
+        assert_eq!(settings.erosion_amount, 4);
+    }
+
+    #[test]
+    fn old_saves_get_a_thumbnail() {
+        let save = SaveFile::read(&corpus_path().join("v1.save")).unwrap();
+        assert_eq!(save.metadata.saved_at, 0);
+        assert_eq!(
+            save.metadata.thumbnail.data,
+            save.heightmap.downscale(THUMBNAIL_SIZE).data
+        );
+    }
+
+    #[test]
+    fn current_version_round_trips() {
+        let mut heightmap = Heightmap::new([1, 1]);
+        heightmap[[3, 4]] = 0.5;
+        let settings = WorldSettings {
+            map_size: MapSize::Custom([1, 1]),
+            custom_noise_layers: Some(vec![NoiseLayer::default()]),
+            erosion: ErosionSettings {
+                gravity: 10.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let rivers = RiverNetwork {
+            lakes: vec![Lake {
+                position: [3, 4],
+                surface: 0.5,
+                area: 1,
+            }],
+            ..Default::default()
+        };
+        let save = SaveFile::new(heightmap.clone(), settings.clone(), rivers.clone());
+        let save = from_str(&ron::to_string(&save).unwrap()).unwrap();
+        assert_eq!(save.heightmap.data, heightmap.data);
+        assert!(save.metadata.world_gen_settings == settings);
+        assert_eq!(save.rivers, rivers);
+    }
+
+    #[test]
+    fn old_saves_have_no_rivers() {
+        let save = SaveFile::read(&corpus_path().join("v2.save")).unwrap();
+        assert_eq!(save.rivers, RiverNetwork::default());
+        assert_eq!(save.metadata.world_gen_settings.river_amount, 0);
+    }
+
+    #[test]
+    fn future_versions_are_rejected() {
+        let text = "(version:99)";
+        assert!(matches!(
+            from_str(text),
+            Err(LoadError::UnsupportedVersion(99))
+        ));
+    }
+
+    #[test]
+    fn mismatched_heightmaps_are_rejected() {
+        let text = fs::read_to_string(corpus_path().join("v1.save"))
+            .unwrap()
+            .replace("size:(129,129)", "size:(130,129)");
+        assert!(matches!(from_str(&text), Err(LoadError::Corrupted(_))));
+    }
+
+    #[test]
+    fn heightmaps_that_dont_fit_the_map_size_are_rejected() {
+        let text = fs::read_to_string(corpus_path().join("v1.save"))
+            .unwrap()
+            .replace("map_size:Custom((1,1))", "map_size:Custom((4,8))");
+        assert!(matches!(from_str(&text), Err(LoadError::Corrupted(_))));
+    }
+}
@@ -1,9 +1,12 @@
 use std::{env::current_dir, fs::File, io::prelude::Write};
 
-use crate::{utils::blur::BLUR_WORKGROUP_SIZE, world_gen::consts::{
-    CHUNK_WORLD_SIZE, EROSION_DISPATCH_SIZE, EROSION_WORKGROUP_SIZE, HEIGHTMAP_CHUNK_SIZE,
-    MAX_EROSION_STEPS,
-}};
+use crate::{
+    utils::blur::BLUR_WORKGROUP_SIZE,
+    world_gen::{
+        consts::{EROSION_DISPATCH_SIZE, EROSION_WORKGROUP_SIZE, MAX_EROSION_STEPS},
+        erosion::thermal_erosion::THERMAL_WORKGROUP_SIZE,
+    },
+};
 
 macro_rules! constant_to_wgsl {
     ($constant:ident) => {
@@ -26,12 +29,8 @@ pub fn create_shader_constants() {
     text.push_str(constant_to_wgsl!(EROSION_DISPATCH_SIZE));
     text.push_str(constant_to_wgsl!(MAX_EROSION_STEPS));
     text.push_str(&format!("const PI = {:};\n", std::f64::consts::PI));
-    text.push_str(&format!(
-        "const HEIGHTMAP_IMAGE_SIZE = vec2<u32>({:},{:});\n",
-        CHUNK_WORLD_SIZE[0] * HEIGHTMAP_CHUNK_SIZE,
-        CHUNK_WORLD_SIZE[1] * HEIGHTMAP_CHUNK_SIZE
-    ));
     text.push_str(constant_to_wgsl!(BLUR_WORKGROUP_SIZE));
+    text.push_str(constant_to_wgsl!(THERMAL_WORKGROUP_SIZE));
 
     //Write the text to the file
     file.write_all(text.as_bytes()).unwrap();
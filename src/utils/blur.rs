@@ -43,3 +43,32 @@ impl ComputeWorker for BlurComputeWorker {
         worker
     }
 }
+
+//Cpu version of blur.wgsl, used when the compute shaders aren't available
+pub fn cpu_blur(image: &[f32], image_size: [u32; 2], blur_size: [u32; 2]) -> Vec<f32> {
+    let radius = [(blur_size[0] / 2) as i32, (blur_size[1] / 2) as i32];
+    let image_size = [image_size[0] as i32, image_size[1] as i32];
+    let mut result = vec![0.0; image.len()];
+    for y in 0..image_size[1] {
+        for x in 0..image_size[0] {
+            let mut sum = 0.0;
+            let mut length = 0;
+            for dx in -radius[0]..=radius[0] {
+                for dy in -radius[1]..=radius[1] {
+                    let sample_pos = [x + dx, y + dy];
+                    if sample_pos[0] < 0
+                        || sample_pos[0] >= image_size[0]
+                        || sample_pos[1] < 0
+                        || sample_pos[1] >= image_size[1]
+                    {
+                        continue;
+                    }
+                    sum += image[(sample_pos[1] * image_size[0] + sample_pos[0]) as usize];
+                    length += 1;
+                }
+            }
+            result[(y * image_size[0] + x) as usize] = sum / length as f32;
+        }
+    }
+    result
+}
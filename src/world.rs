@@ -1,14 +1,25 @@
 use std::f32::consts::PI;
 
-use crate::{world_gen::consts::WORLD_HEIGHT_SCALE, GameState};
+use crate::{camera::CameraRaycastSet, world_gen::consts::WORLD_HEIGHT_SCALE, GameState};
 use bevy::prelude::*;
+
+use self::terraforming::{exit_terraforming, init_terraforming, terraform, terraforming_ui};
+
+pub mod terraforming;
+
 pub struct WorldPlugin;
 
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, init);
-        app.add_systems(OnEnter(GameState::World), setup);
-        app.add_systems(OnExit(GameState::World), exit);
+        app.add_systems(OnEnter(GameState::World), (setup, init_terraforming));
+        app.add_systems(
+            Update,
+            (terraforming_ui, terraform.after(CameraRaycastSet))
+                .chain()
+                .run_if(in_state(GameState::World)),
+        );
+        app.add_systems(OnExit(GameState::World), (exit, exit_terraforming));
     }
 }
 
@@ -0,0 +1,423 @@
+use std::{collections::HashMap, ops::DerefMut};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use crate::{
+    camera::TerrainCursor,
+    world_gen::{
+        consts::WORLD_HEIGHT_SCALE,
+        heightmap::{edit::HeightmapEdit, Heightmap},
+    },
+};
+
+const MAX_BRUSH_RADIUS: u32 = 32;
+//How fast raising and lowering move the ground at full strength, in world units per second
+const MAX_RAISE_SPEED: f32 = 40.0;
+//How much of the way to their target flattening and smoothing pull the heights each second at full strength
+const MAX_BLEND_SPEED: f32 = 10.0;
+//Strokes further back than this can't be undone
+const MAX_UNDO_STEPS: usize = 32;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, EnumIter)]
+pub enum TerraformTool {
+    #[default]
+    Raise,
+    Lower,
+    //Pulls the ground towards the height it was at where the stroke started
+    Flatten,
+    //Pulls every point towards the average of its neighbours
+    Smooth,
+    //Drag from one point to another to make a ramp between their heights
+    Level,
+}
+
+impl TerraformTool {
+    pub fn name(self) -> &'static str {
+        match self {
+            TerraformTool::Raise => "Raise",
+            TerraformTool::Lower => "Lower",
+            TerraformTool::Flatten => "Flatten",
+            TerraformTool::Smooth => "Smooth",
+            TerraformTool::Level => "Level",
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct Terraforming {
+    pub tool: TerraformTool,
+    //In tiles
+    pub radius: u32,
+    //From 0 to 1
+    pub strength: f32,
+    //The point and height the current stroke started at
+    anchor: Option<([u32; 2], f32)>,
+    //The heights from before the current stroke, for every point it changed
+    stroke: HashMap<[u32; 2], f32>,
+    undo: Vec<Vec<([u32; 2], f32)>>,
+}
+
+impl Default for Terraforming {
+    fn default() -> Self {
+        Self {
+            tool: TerraformTool::default(),
+            radius: 4,
+            strength: 0.5,
+            anchor: None,
+            stroke: HashMap::new(),
+            undo: Vec::new(),
+        }
+    }
+}
+
+impl Terraforming {
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+    //Whether a stroke is being painted, the heightmap changes every frame until it ends
+    pub fn is_painting(&self) -> bool {
+        self.anchor.is_some()
+    }
+    fn start_stroke(&mut self, heightmap: &Heightmap, point: [u32; 2]) {
+        self.finish_stroke();
+        self.anchor = Some((point, heightmap[point]));
+    }
+    //The stroke becomes a single undo step, however many frames it was painted over
+    fn finish_stroke(&mut self) {
+        self.anchor = None;
+        if self.stroke.is_empty() {
+            return;
+        }
+        self.undo.push(self.stroke.drain().collect());
+        if self.undo.len() > MAX_UNDO_STEPS {
+            self.undo.remove(0);
+        }
+    }
+    //Writes the new heights, keeping the old ones for undoing the stroke
+    //The heightmap is only borrowed mutably when there is something to write, so an empty edit doesn't mark it changed,
+    //as a change without an edit remakes every chunk
+    fn set_heights(
+        &mut self,
+        mut heightmap: impl DerefMut<Target = Heightmap>,
+        heights: Vec<([u32; 2], f32)>,
+    ) -> Option<HeightmapEdit> {
+        let edit = HeightmapEdit::from_points(heights.iter().map(|&(point, _)| point))?;
+        for (point, height) in heights {
+            self.stroke.entry(point).or_insert(heightmap[point]);
+            heightmap[point] = height.clamp(0.0, 1.0);
+        }
+        Some(edit)
+    }
+    //Applies the brush tools under the cursor, delta is the time the button was held for this frame
+    fn paint(
+        &mut self,
+        heightmap: impl DerefMut<Target = Heightmap>,
+        centre: [u32; 2],
+        delta: f32,
+    ) -> Option<HeightmapEdit> {
+        let heights = self.brush_heights(&heightmap, centre, delta);
+        self.set_heights(heightmap, heights)
+    }
+    fn brush_heights(
+        &self,
+        heightmap: &Heightmap,
+        centre: [u32; 2],
+        delta: f32,
+    ) -> Vec<([u32; 2], f32)> {
+        let footprint = brush_footprint(heightmap, centre, self.radius);
+        let raise = self.strength * MAX_RAISE_SPEED * delta / WORLD_HEIGHT_SCALE;
+        let blend = self.strength * MAX_BLEND_SPEED * delta;
+        match self.tool {
+            TerraformTool::Raise | TerraformTool::Lower => {
+                let raise = if self.tool == TerraformTool::Raise {
+                    raise
+                } else {
+                    -raise
+                };
+                footprint
+                    .into_iter()
+                    .map(|(point, weight)| (point, heightmap[point] + raise * weight))
+                    .collect()
+            }
+            TerraformTool::Flatten => {
+                let target = self.anchor.map_or(heightmap[centre], |(_, height)| height);
+                footprint
+                    .into_iter()
+                    .map(|(point, weight)| {
+                        let height = heightmap[point];
+                        (
+                            point,
+                            height + (target - height) * (blend * weight).min(1.0),
+                        )
+                    })
+                    .collect()
+            }
+            TerraformTool::Smooth => footprint
+                .into_iter()
+                .map(|(point, weight)| {
+                    let height = heightmap[point];
+                    let (total, count) = heightmap
+                        .neighbours(point)
+                        .fold((height, 1.0), |(total, count), neighbour| {
+                            (total + heightmap[neighbour], count + 1.0)
+                        });
+                    let average = total / count;
+                    (
+                        point,
+                        height + (average - height) * (blend * weight).min(1.0),
+                    )
+                })
+                .collect(),
+            //Applied once the drag ends
+            TerraformTool::Level => Vec::new(),
+        }
+    }
+    //Ramps the ground under a line from where the stroke started to the end, the strength is ignored
+    fn level(
+        &mut self,
+        heightmap: impl DerefMut<Target = Heightmap>,
+        end: [u32; 2],
+    ) -> Option<HeightmapEdit> {
+        let (start, start_height) = self.anchor?;
+        let end_height = heightmap[end];
+        let start_position = Vec2::new(start[0] as f32, start[1] as f32);
+        let line = Vec2::new(end[0] as f32, end[1] as f32) - start_position;
+        //Brush footprints along the line, close enough together that they overlap
+        let steps = (line.length() / (self.radius as f32).max(1.0)).ceil() as u32;
+        let mut points = (0..=steps)
+            .flat_map(|step| {
+                let position = start_position + line * step as f32 / steps.max(1) as f32;
+                let point = [position.x.round() as u32, position.y.round() as u32];
+                brush_footprint(&heightmap, point, self.radius)
+            })
+            .map(|(point, _)| point)
+            .collect::<Vec<_>>();
+        points.sort_unstable();
+        points.dedup();
+        let radius = self.radius as f32 + 1.0;
+        let heights = points
+            .into_iter()
+            .filter_map(|point| {
+                let position = Vec2::new(point[0] as f32, point[1] as f32) - start_position;
+                let along = if line == Vec2::ZERO {
+                    0.0
+                } else {
+                    (position.dot(line) / line.length_squared()).clamp(0.0, 1.0)
+                };
+                let distance = position.distance(line * along);
+                if distance >= radius {
+                    return None;
+                }
+                let weight = 1.0 - distance / radius;
+                let target = start_height + (end_height - start_height) * along;
+                let height = heightmap[point];
+                Some((point, height + (target - height) * weight))
+            })
+            .collect();
+        self.set_heights(heightmap, heights)
+    }
+    fn undo(&mut self, mut heightmap: impl DerefMut<Target = Heightmap>) -> Option<HeightmapEdit> {
+        self.finish_stroke();
+        let heights = self.undo.pop()?;
+        for &(point, height) in &heights {
+            heightmap[point] = height;
+        }
+        HeightmapEdit::from_points(heights.into_iter().map(|(point, _)| point))
+    }
+}
+
+//The points under the brush, with how strongly it affects each one, fading out towards the edge
+fn brush_footprint(heightmap: &Heightmap, centre: [u32; 2], radius: u32) -> Vec<([u32; 2], f32)> {
+    let radius = radius as f32 + 1.0;
+    heightmap
+        .get_circle(centre, radius as u32)
+        .filter_map(|point| {
+            let distance = Vec2::new(
+                point[0] as f32 - centre[0] as f32,
+                point[1] as f32 - centre[1] as f32,
+            )
+            .length();
+            (distance < radius).then(|| (point, 1.0 - distance / radius))
+        })
+        .collect()
+}
+
+pub fn init_terraforming(mut commands: Commands) {
+    commands.init_resource::<Terraforming>();
+}
+
+pub fn exit_terraforming(mut commands: Commands) {
+    commands.remove_resource::<Terraforming>();
+}
+
+//Left click paints with the selected tool on the terrain under the cursor, ctrl + z undoes the last stroke
+pub fn terraform(
+    mut terraforming: ResMut<Terraforming>,
+    mut heightmap: ResMut<Heightmap>,
+    mut heightmap_edits: EventWriter<HeightmapEdit>,
+    terrain_cursor: Res<TerrainCursor>,
+    mouse_buttons: Res<ButtonInput<MouseButton>>,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    mut contexts: EguiContexts,
+    mut gizmos: Gizmos,
+) {
+    let control = keyboard.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if control && keyboard.just_pressed(KeyCode::KeyZ) {
+        heightmap_edits.send_batch(terraforming.undo(heightmap.reborrow()));
+        return;
+    }
+    if mouse_buttons.just_released(MouseButton::Left) && terraforming.anchor.is_some() {
+        let level = terraforming.tool == TerraformTool::Level;
+        if let Some(end) = terrain_cursor.hovered.filter(|_| level) {
+            heightmap_edits.send_batch(terraforming.level(heightmap.reborrow(), end.point()));
+        }
+        terraforming.finish_stroke();
+        return;
+    }
+    let Some(point) = terrain_cursor.hovered.map(|hovered| hovered.point()) else {
+        return;
+    };
+    let position = |point: [u32; 2]| {
+        Vec3::new(
+            point[0] as f32,
+            heightmap[point] * WORLD_HEIGHT_SCALE,
+            point[1] as f32,
+        )
+    };
+    gizmos.circle(
+        position(point),
+        Direction3d::Y,
+        terraforming.radius as f32 + 1.0,
+        Color::WHITE,
+    );
+    if let Some((start, _)) = terraforming.anchor {
+        if terraforming.tool == TerraformTool::Level {
+            gizmos.line(position(start), position(point), Color::YELLOW);
+        }
+    }
+    //Clicks on the UI don't start strokes
+    if mouse_buttons.just_pressed(MouseButton::Left) && !contexts.ctx_mut().is_pointer_over_area() {
+        terraforming.start_stroke(&heightmap, point);
+    }
+    //Level is applied when the drag ends, there is nothing to paint while it's held
+    if mouse_buttons.pressed(MouseButton::Left)
+        && terraforming.anchor.is_some()
+        && terraforming.tool != TerraformTool::Level
+    {
+        heightmap_edits.send_batch(terraforming.paint(
+            heightmap.reborrow(),
+            point,
+            time.delta_seconds(),
+        ));
+    }
+}
+
+pub fn terraforming_ui(
+    mut contexts: EguiContexts,
+    mut terraforming: ResMut<Terraforming>,
+    mut heightmap: ResMut<Heightmap>,
+    mut heightmap_edits: EventWriter<HeightmapEdit>,
+) {
+    let ctx = contexts.ctx_mut();
+    egui::Window::new("Terraforming")
+        .resizable(false)
+        .show(ctx, |ui| {
+            egui::Grid::new("Terraforming_Grid").show(ui, |ui| {
+                ui.label("Tool");
+                egui::ComboBox::from_id_source("Terraform_Tool")
+                    .selected_text(terraforming.tool.name())
+                    .show_ui(ui, |ui| {
+                        for tool in TerraformTool::iter() {
+                            ui.selectable_value(&mut terraforming.tool, tool, tool.name());
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Radius");
+                ui.add(
+                    egui::Slider::new(&mut terraforming.radius, 0..=MAX_BRUSH_RADIUS)
+                        .clamp_to_range(true),
+                );
+                ui.end_row();
+
+                ui.label("Strength");
+                ui.add_enabled(
+                    terraforming.tool != TerraformTool::Level,
+                    egui::Slider::new(&mut terraforming.strength, 0.0..=1.0).clamp_to_range(true),
+                );
+                ui.end_row();
+            });
+            if ui
+                .add_enabled(terraforming.can_undo(), egui::Button::new("Undo"))
+                .clicked()
+            {
+                heightmap_edits.send_batch(terraforming.undo(heightmap.reborrow()));
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undoing_a_stroke_restores_every_frame_of_it() {
+        let mut heightmap = Heightmap::new([1, 1]);
+        let mut terraforming = Terraforming::default();
+        terraforming.start_stroke(&heightmap, [20, 20]);
+        let edit = terraforming.paint(&mut heightmap, [20, 20], 0.1).unwrap();
+        terraforming.paint(&mut heightmap, [22, 20], 0.1);
+        terraforming.finish_stroke();
+        assert!(heightmap[[20, 20]] > 0.0);
+        assert!(edit.min[0] <= 20 - terraforming.radius && edit.max[0] >= 20 + terraforming.radius);
+        let undo = terraforming.undo(&mut heightmap).unwrap();
+        assert!(heightmap.data.iter().all(|&height| height == 0.0));
+        assert!(undo.max[0] >= 22 + terraforming.radius);
+        assert!(!terraforming.can_undo());
+    }
+
+    #[test]
+    fn levelling_ramps_between_the_ends() {
+        let mut heightmap = Heightmap::new([1, 1]);
+        heightmap[[60, 30]] = 0.5;
+        let mut terraforming = Terraforming {
+            tool: TerraformTool::Level,
+            ..Default::default()
+        };
+        terraforming.start_stroke(&heightmap, [20, 30]);
+        terraforming.level(&mut heightmap, [60, 30]);
+        assert!(heightmap[[20, 30]] == 0.0);
+        assert!((heightmap[[40, 30]] - 0.25).abs() < 1e-6);
+        assert!((heightmap[[60, 30]] - 0.5).abs() < 1e-6);
+        //Fades out to the sides of the line
+        assert!(heightmap[[40, 32]] > 0.0 && heightmap[[40, 32]] < 0.25);
+    }
+
+    #[test]
+    fn level_drags_dont_remesh_every_chunk() {
+        //The meshes are all remade when the heightmap changes without an edit
+        let mut world = World::new();
+        world.insert_resource(Heightmap::new([1, 1]));
+        world.clear_trackers();
+        let mut terraforming = Terraforming {
+            tool: TerraformTool::Level,
+            ..Default::default()
+        };
+        terraforming.start_stroke(world.resource::<Heightmap>(), [20, 30]);
+        for point in [[30, 30], [40, 30]] {
+            let heightmap = world.resource_mut::<Heightmap>();
+            assert_eq!(terraforming.paint(heightmap, point, 0.1), None);
+        }
+        //Nothing to undo yet either
+        assert_eq!(terraforming.undo(world.resource_mut::<Heightmap>()), None);
+        assert!(!world.is_resource_changed::<Heightmap>());
+        //The ramp is made when the drag ends, with an edit for just the points under it
+        let edit = terraforming.level(world.resource_mut::<Heightmap>(), [40, 30]);
+        assert!(edit.is_some());
+        assert!(world.is_resource_changed::<Heightmap>());
+    }
+}
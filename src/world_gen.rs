@@ -7,26 +7,61 @@ use bevy::{
 use bevy_app_compute::prelude::{AppComputeWorker, AppComputeWorkerPlugin};
 use egui_file::FileDialog;
 use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
 
 pub mod consts;
 pub mod erosion;
 pub mod heightmap;
 pub mod mesh_gen;
 pub mod noise_gen;
+pub mod pipeline;
+pub mod presets;
+pub mod rivers;
 pub mod terrain_material;
 
+#[cfg(test)]
+mod golden_tests;
+
 use crate::{
-    save::{save_path, SaveEvent},
+    save::{save_path, SaveEvent, SaveFinished},
     utils::math::AsF32,
+    world::WorldSize,
     GameState,
 };
 
 use self::{
-    consts::{CHUNK_WORLD_SIZE, HEIGHTMAP_CHUNK_SIZE, WORLD_HEIGHT_SCALE},
-    erosion::{gpu_erode_heightmap, ErosionComputeFields, ErosionComputeWorker, ErosionEvent},
-    heightmap::{Heightmap, HeightmapImage},
-    mesh_gen::{generate_world_mesh, level_of_detail},
-    noise_gen::{noise_function, NoiseFunction, NoiseSettings},
+    consts::{
+        CHUNK_SIZE, HEIGHTMAP_CHUNK_SIZE, LARGE_CHUNK_WORLD_SIZE, MAX_CHUNK_WORLD_SIZE,
+        MEDIUM_CHUNK_WORLD_SIZE, SMALL_CHUNK_WORLD_SIZE, WORLD_HEIGHT_SCALE,
+    },
+    erosion::{
+        cpu_erosion::{cpu_erode_heightmap, cpu_smooth_heightmap},
+        gpu_erode_heightmap, gpu_erosion_available, gpu_smooth_heightmap,
+        thermal_erosion::{ThermalErosionComputeWorker, ThermalErosionSettings},
+        ErosionComputeFields, ErosionComputeWorker, ErosionSettings,
+    },
+    heightmap::{
+        edit::HeightmapEdit,
+        export::{export_heightmap, ExportFormat},
+        hydrology::{update_hydrology, Hydrology},
+        import::{import_heightmap, ImportedHeightmap, IMPORT_EXTENSIONS},
+        rgba_to_bevy_image, Heightmap, HeightmapImage,
+    },
+    mesh_gen::{
+        chunk_cache::{exit_chunk_meshes, init_chunk_meshes, level_of_detail, update_chunk_meshes},
+        generate_world_mesh,
+    },
+    noise_gen::{
+        continent_mask::ContinentShape,
+        layers::{default_layers, LayerBlend, LayerGenerator, LayerMask, NoiseLayer},
+        noise_function, NoiseFunction, NoiseSettings,
+    },
+    pipeline::{finalize_generation, GenerationStage, StageStatus, WorldGenPipeline},
+    presets::{
+        apply_preset, builtin_presets, initalize_presets, list_presets, save_preset, WorldPreset,
+    },
+    rivers::generate_river_network,
+    terrain_material::ExtendedTerrainMaterial,
 };
 use bevy_egui::{
     egui::{self, TextureId},
@@ -37,6 +72,7 @@ use bevy_egui::{
 // 1. Generate height map # DONE
 // 2. Generate mesh from height map # DONE
 // 2a. Generate water mesh from height map
+// 2b. Carve rivers and lakes into the height map # DONE
 // 3. Generate ground textures from height map # DONE
 // 4. Spawn trees
 
@@ -44,21 +80,46 @@ pub struct WorldGenPlugin;
 
 impl Plugin for WorldGenPlugin {
     fn build(&self, app: &mut App) {
-        app.add_event::<ErosionEvent>();
         app.add_plugins(AppComputeWorkerPlugin::<ErosionComputeWorker>::default());
+        app.add_plugins(AppComputeWorkerPlugin::<ThermalErosionComputeWorker>::default());
+        app.add_plugins(MaterialPlugin::<ExtendedTerrainMaterial>::default());
+        app.add_event::<HeightmapEdit>();
+        app.add_systems(Startup, init_presets);
         app.add_systems(OnEnter(GameState::WorldGeneration), init);
         app.add_systems(
             Update,
             (
-                generate_heightmap,
-                display_ui,
-                (update_heightmap_image, gpu_erode_heightmap).chain(),
+                (import_ui, export_ui, presets_ui, display_ui).chain(),
+                (
+                    restart_generation,
+                    generate_heightmap,
+                    update_heightmap_image,
+                    (gpu_erode_heightmap, gpu_smooth_heightmap)
+                        .chain()
+                        .run_if(gpu_erosion_available),
+                    (cpu_erode_heightmap, cpu_smooth_heightmap)
+                        .chain()
+                        .run_if(not(gpu_erosion_available)),
+                    generate_river_network,
+                    finalize_generation,
+                )
+                    .chain(),
             )
                 .run_if(in_state(GameState::WorldGeneration)),
         );
+        app.add_systems(OnEnter(GameState::World), init_chunk_meshes);
+        app.add_systems(
+            Update,
+            (generate_world_mesh, update_chunk_meshes, level_of_detail)
+                .chain()
+                .run_if(in_state(GameState::World)),
+        );
+        app.add_systems(OnExit(GameState::World), exit_chunk_meshes);
         app.add_systems(
             Update,
-            (generate_world_mesh, level_of_detail).run_if(in_state(GameState::World)),
+            update_hydrology
+                .run_if(resource_exists::<Heightmap>)
+                .after(finalize_generation),
         );
         app.add_systems(OnExit(GameState::WorldGeneration), exit);
     }
@@ -66,33 +127,121 @@ impl Plugin for WorldGenPlugin {
 
 fn update_heightmap_image(
     mut heightmap: ResMut<Heightmap>,
-    heightmap_image: ResMut<HeightmapImage>,
+    mut heightmap_image: ResMut<HeightmapImage>,
     world_settings: Res<WorldSettings>,
-    progress_bar: Res<HeightmapLoadBar>,
-    erosion_worker: ResMut<AppComputeWorker<ErosionComputeWorker>>,
+    pipeline: Res<WorldGenPipeline>,
+    erosion_worker: Option<Res<AppComputeWorker<ErosionComputeWorker>>>,
+    overlay: Res<PreviewOverlay>,
+    hydrology: Option<Res<Hydrology>>,
     mut image_assets: ResMut<Assets<Image>>,
     mut counter: Local<u8>,
 ) {
     *counter = counter.saturating_add(1);
-    if (*counter > 10 || progress_bar.heightmap_progress < 1.0) && erosion_worker.ready() {
+    if *counter > 10 || pipeline.running(GenerationStage::Noise) {
         //Updates the heightmap image every five frames from the erosion gpu buffer if its avaliable
-        let results: Vec<f32> = erosion_worker.read_vec(ErosionComputeFields::Results);
-        heightmap.data = results;
+        //The cpu erosion writes straight into the heightmap, so there is nothing to read back
+        //Once erosion is done the buffer is older than the smoothed and carved heightmap
+        let erosion_worker = erosion_worker.filter(|_| pipeline.running(GenerationStage::Erosion));
+        if let Some(erosion_worker) = erosion_worker {
+            if !erosion_worker.ready() {
+                return;
+            }
+            let results: Vec<f32> = erosion_worker.read_vec(ErosionComputeFields::Results);
+            //The buffer is stale if the map size changed since the last erosion
+            if results.len() == heightmap.data.len() {
+                heightmap.data = results;
+            }
+        }
 
         let old_image = image_assets
             .get_mut(heightmap_image.image.clone_weak())
             .unwrap();
-        let new_image = heightmap.clone().as_bevy_image(&world_settings);
-        *old_image = new_image;
+        let mut new_image = heightmap.as_rgba_image(&world_settings);
+        if let (PreviewOverlay::Drainage, Some(hydrology)) = (*overlay, &hydrology) {
+            //The hydrology is recomputed in the background, so it can be from an older map size
+            if hydrology.size() == heightmap.size() {
+                hydrology.draw_overlay(&mut new_image);
+            }
+        }
+        *old_image = rgba_to_bevy_image(new_image);
+        heightmap_image.size = heightmap.size().into();
         *counter = 0;
     }
 }
 
-#[derive(Resource, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+//Extra information drawn over the heightmap preview
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PreviewOverlay {
+    #[default]
+    None,
+    //How much water flows through each point
+    Drainage,
+}
+
+impl PreviewOverlay {
+    pub const ALL: [PreviewOverlay; 2] = [PreviewOverlay::None, PreviewOverlay::Drainage];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PreviewOverlay::None => "None",
+            PreviewOverlay::Drainage => "Drainage",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MapSize {
+    Small,
+    #[default]
+    Medium,
+    Large,
+    Custom(WorldSize),
+}
+
+impl MapSize {
+    pub const PRESETS: [MapSize; 3] = [MapSize::Small, MapSize::Medium, MapSize::Large];
+
+    //Size of the world in chunks
+    pub fn chunks(self) -> WorldSize {
+        match self {
+            MapSize::Small => SMALL_CHUNK_WORLD_SIZE,
+            MapSize::Medium => MEDIUM_CHUNK_WORLD_SIZE,
+            MapSize::Large => LARGE_CHUNK_WORLD_SIZE,
+            MapSize::Custom([x, y]) => [
+                x.clamp(1, MAX_CHUNK_WORLD_SIZE),
+                y.clamp(1, MAX_CHUNK_WORLD_SIZE),
+            ],
+        }
+    }
+    pub fn name(self) -> String {
+        let [x, y] = self.chunks();
+        match self {
+            MapSize::Small => format!("Small ({x}x{y})"),
+            MapSize::Medium => format!("Medium ({x}x{y})"),
+            MapSize::Large => format!("Large ({x}x{y})"),
+            MapSize::Custom(_) => "Custom".to_string(),
+        }
+    }
+}
+
+#[derive(Resource, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct WorldSettings {
     pub noise_settings: NoiseSettings,
     pub water_level: u32,
     pub erosion_amount: u32,
+    //Older saves use the default erosion
+    #[serde(default)]
+    pub erosion: ErosionSettings,
+    pub map_size: MapSize,
+    //Older saves don't have rivers
+    #[serde(default)]
+    pub river_amount: u32,
+    //Older saves blur the erosion instead
+    #[serde(default)]
+    pub thermal_erosion: ThermalErosionSettings,
+    //Replaces the default layers built from the noise settings, older saves don't have this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_noise_layers: Option<Vec<NoiseLayer>>,
 }
 
 impl Default for WorldSettings {
@@ -100,106 +249,332 @@ impl Default for WorldSettings {
         Self {
             noise_settings: NoiseSettings::default(),
             erosion_amount: 0,
+            erosion: ErosionSettings::default(),
             water_level: 10,
+            map_size: MapSize::default(),
+            river_amount: 0,
+            thermal_erosion: ThermalErosionSettings::default(),
+            custom_noise_layers: None,
         }
     }
 }
 
 impl WorldSettings {
-    fn seed(&self) -> u32 {
+    pub fn seed(&self) -> u32 {
         self.noise_settings.seed
     }
-}
-
-#[derive(Resource, Default)]
-pub struct HeightmapLoadBar {
-    heightmap_progress: f32,
-    erosion_progress: f32,
-}
-impl HeightmapLoadBar {
-    pub fn progress(&self) -> f32 {
-        (self.heightmap_progress + self.erosion_progress) / 2.0
+    pub fn noise_layers(&self) -> Vec<NoiseLayer> {
+        self.custom_noise_layers
+            .clone()
+            .unwrap_or_else(|| default_layers(&self.noise_settings))
+    }
+    //Size of the world in chunks
+    pub fn world_size(&self) -> WorldSize {
+        self.map_size.chunks()
+    }
+    //Size of the world in tiles
+    pub fn tile_world_size(&self) -> WorldSize {
+        let [x, y] = self.world_size();
+        [x * CHUNK_SIZE, y * CHUNK_SIZE]
     }
 }
 
 fn init(mut commands: Commands, mut image_assets: ResMut<Assets<Image>>) {
     commands.init_resource::<WorldSettings>();
-    let heightmap = Heightmap::new(CHUNK_WORLD_SIZE);
+    let heightmap = Heightmap::new(WorldSettings::default().world_size());
     commands.insert_resource(HeightmapImage {
         image: image_assets.add(heightmap.clone().as_bevy_image(&WorldSettings::default())),
         size: heightmap.size().into(),
     });
     commands.insert_resource(heightmap);
-    commands.init_resource::<HeightmapLoadBar>();
+    //Starts generating straight away, the settings are checked for changes the same frame
+    commands.insert_resource(WorldGenPipeline::new(WorldSettings::default()));
+    commands.init_resource::<PreviewOverlay>();
 }
 
 fn exit(mut commands: Commands) {
-    commands.remove_resource::<HeightmapLoadBar>();
+    commands.remove_resource::<WorldGenPipeline>();
+    commands.remove_resource::<PreviewOverlay>();
+    commands.remove_resource::<ImportedHeightmap>();
+}
+
+//Starts generation over whenever the settings change, cancelled generation waits for the user instead
+//Importing a heightmap always starts over, since it is never done by accident
+fn restart_generation(
+    world_settings: Res<WorldSettings>,
+    imported_heightmap: Option<Res<ImportedHeightmap>>,
+    mut was_imported: Local<bool>,
+    mut pipeline: ResMut<WorldGenPipeline>,
+) {
+    let import_changed = imported_heightmap
+        .as_ref()
+        .map_or(*was_imported, |imported| imported.is_changed());
+    *was_imported = imported_heightmap.is_some();
+    let settings_changed = *pipeline.settings() != *world_settings;
+    if import_changed || (settings_changed && !pipeline.is_cancelled()) {
+        pipeline.restart(world_settings.clone());
+    }
 }
 
 fn generate_heightmap(
     mut heightmap: ResMut<Heightmap>,
-    world_settings: Res<WorldSettings>,
+    mut pipeline: ResMut<WorldGenPipeline>,
     mut tasks: Local<Vec<Task<Vec<([u32; 2], f64)>>>>,
-    mut previous_world_settings: Local<Option<WorldSettings>>,
-    mut heightmap_load_bar: ResMut<HeightmapLoadBar>,
-    mut erosion_event: EventWriter<ErosionEvent>,
-    mut working: Local<bool>,
+    mut run: Local<Option<u32>>,
+    imported_heightmap: Option<Res<ImportedHeightmap>>,
 ) {
-    if *working {
-        if tasks.is_empty() {
-            heightmap_load_bar.heightmap_progress = 0.0;
-        } else {
-            //Update the load bar
-            heightmap_load_bar.heightmap_progress =
-                tasks.iter().filter(|task| task.is_finished()).count() as f32 / tasks.len() as f32;
+    match pipeline.status(GenerationStage::Noise, &mut run) {
+        StageStatus::Inactive => {
+            //Dropping the tasks cancels them
+            tasks.clear();
+            return;
+        }
+        StageStatus::Started => {
+            tasks.clear();
+            let world_settings = pipeline.settings().clone();
+            let world_size = world_settings.world_size();
+
+            //Imported heightmaps replace the noise, but are still eroded
+            if let Some(imported) = imported_heightmap {
+                *heightmap = imported.heightmap.resampled(world_size);
+                pipeline.advance(&heightmap);
+                return;
+            }
+
+            let size = world_size.map(|chunks| chunks * HEIGHTMAP_CHUNK_SIZE);
+            if heightmap.size() != size {
+                *heightmap = Heightmap::new(world_size);
+            }
+
+            //Seperate each chunk into its own task to be processed in parallel, and over multiple frames
+            let thread_pool = AsyncComputeTaskPool::get();
+            for chunk_y in 0..world_size[1] {
+                for chunk_x in 0..world_size[0] {
+                    let world_settings = world_settings.clone();
+                    let task = thread_pool.spawn(async move {
+                        let perlin = noise_function(&world_settings);
+                        let mut results = Vec::with_capacity(
+                            (HEIGHTMAP_CHUNK_SIZE * HEIGHTMAP_CHUNK_SIZE) as usize,
+                        );
+                        for x in 0..HEIGHTMAP_CHUNK_SIZE {
+                            for y in 0..HEIGHTMAP_CHUNK_SIZE {
+                                let x = x + chunk_x * HEIGHTMAP_CHUNK_SIZE;
+                                let y = y + chunk_y * HEIGHTMAP_CHUNK_SIZE;
+                                let result = ([x, y], perlin.get([x, y]));
+                                results.push(result);
+                            }
+                        }
+                        results
+                    });
+                    tasks.push(task);
+                }
+            }
+            //Gives the tasks a frame of processing time before checking them
+            return;
         }
+        StageStatus::Running => {}
     }
 
-    //Checks tasks first to give one frame of processing time to the tasks
-    if heightmap_load_bar.heightmap_progress >= 1.0 && *working {
-        //Tasks are finished, process the results
-        for task in &mut tasks {
-            let result = block_on(task);
-            for (index, noise) in result {
+    let finished = tasks.iter().filter(|task| task.is_finished()).count();
+    pipeline.set_progress(finished as f32 / tasks.len().max(1) as f32);
+    if finished == tasks.len() {
+        for task in tasks.drain(..) {
+            for (index, noise) in block_on(task) {
                 heightmap[index] = noise as f32;
             }
         }
-        tasks.clear();
-        *working = false;
-        //Trigger the erosion event
-        erosion_event.send(ErosionEvent);
+        pipeline.advance(&heightmap);
     }
+}
 
-    if previous_world_settings.is_none() || *world_settings != previous_world_settings.unwrap() {
-        *working = true;
-        tasks.clear();
-        let thread_pool = AsyncComputeTaskPool::get();
-        let noise_settings = world_settings.noise_settings;
-
-        //Seperate each chunk into its own task to be processed in parallel, and over multiple frames
-        for chunk_y in 0..CHUNK_WORLD_SIZE[1] {
-            for chunk_x in 0..CHUNK_WORLD_SIZE[0] {
-                let task = thread_pool.spawn(async move {
-                    let perlin = noise_function(noise_settings);
-                    let mut results =
-                        Vec::with_capacity((HEIGHTMAP_CHUNK_SIZE * HEIGHTMAP_CHUNK_SIZE) as usize);
-                    for x in 0..HEIGHTMAP_CHUNK_SIZE {
-                        for y in 0..HEIGHTMAP_CHUNK_SIZE {
-                            let x = x + chunk_x * HEIGHTMAP_CHUNK_SIZE;
-                            let y = y + chunk_y * HEIGHTMAP_CHUNK_SIZE;
-                            let result = ([x, y], perlin.get([x, y]));
-                            results.push(result);
-                        }
+fn export_ui(
+    mut contexts: EguiContexts,
+    heightmap: Res<Heightmap>,
+    world_settings: Res<WorldSettings>,
+    pipeline: Res<WorldGenPipeline>,
+    mut export_format: Local<ExportFormat>,
+    mut file_dialog: Local<Option<FileDialog>>,
+    mut export_status: Local<Option<String>>,
+) {
+    let ctx = contexts.ctx_mut();
+    egui::TopBottomPanel::bottom("Heightmap_Export").show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("Export_Format")
+                .selected_text(export_format.name())
+                .show_ui(ui, |ui| {
+                    for format in ExportFormat::ALL {
+                        ui.selectable_value(&mut *export_format, format, format.name());
                     }
-                    results
                 });
-                tasks.push(task);
+            let finished = pipeline.is_finished();
+            if ui
+                .add_enabled(finished, egui::Button::new("Export Heightmap"))
+                .clicked()
+                && file_dialog.is_none()
+            {
+                let mut dialog = FileDialog::save_file(None)
+                    .show_new_folder(false)
+                    .show_rename(false);
+                #[cfg(windows)]
+                {
+                    dialog = dialog.show_drives(false);
+                }
+                dialog.open();
+                *file_dialog = Some(dialog);
+            }
+            if let Some(export_status) = export_status.as_ref() {
+                ui.label(export_status);
+            }
+        });
+    });
+
+    if file_dialog.is_some() {
+        let dialog = file_dialog.as_mut().unwrap();
+        dialog.show(ctx);
+        match dialog.state() {
+            egui_file::State::Open => {}
+            egui_file::State::Closed | egui_file::State::Cancelled => {
+                *file_dialog = None;
+            }
+            egui_file::State::Selected => {
+                let mut path = PathBuf::from(dialog.path().unwrap());
+                path.set_extension(export_format.extension());
+                let start_time = std::time::Instant::now();
+                *export_status = Some(
+                    match export_heightmap(&heightmap, &world_settings, &path, *export_format) {
+                        Ok(()) => {
+                            println!("Export took: {:?}", start_time.elapsed());
+                            format!("Exported to {}", path.display())
+                        }
+                        Err(error) => error.to_string(),
+                    },
+                );
+                *file_dialog = None;
             }
         }
     }
+}
 
-    *previous_world_settings = Some(world_settings.clone());
+fn import_ui(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    imported_heightmap: Option<Res<ImportedHeightmap>>,
+    mut file_dialog: Local<Option<FileDialog>>,
+    mut import_error: Local<Option<String>>,
+) {
+    let ctx = contexts.ctx_mut();
+    egui::TopBottomPanel::bottom("Heightmap_Import").show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            if ui.button("Import Heightmap").clicked() && file_dialog.is_none() {
+                let mut dialog = FileDialog::open_file(None)
+                    .show_new_folder(false)
+                    .show_rename(false)
+                    .show_files_filter(Box::new(|str: &Path| {
+                        let extension = str
+                            .extension()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .to_lowercase();
+                        IMPORT_EXTENSIONS.contains(&extension.as_str())
+                    }));
+                #[cfg(windows)]
+                {
+                    dialog = dialog.show_drives(false);
+                }
+                dialog.open();
+                *file_dialog = Some(dialog);
+            }
+            if let Some(imported) = &imported_heightmap {
+                ui.label(format!("Using {}", imported.name));
+                if ui.button("Use Noise").clicked() {
+                    commands.remove_resource::<ImportedHeightmap>();
+                }
+            }
+            if let Some(import_error) = import_error.as_ref() {
+                ui.colored_label(egui::Color32::RED, import_error);
+            }
+        });
+    });
+
+    if file_dialog.is_some() {
+        let dialog = file_dialog.as_mut().unwrap();
+        dialog.show(ctx);
+        match dialog.state() {
+            egui_file::State::Open => {}
+            egui_file::State::Closed | egui_file::State::Cancelled => {
+                *file_dialog = None;
+            }
+            egui_file::State::Selected => {
+                let path = PathBuf::from(dialog.path().unwrap());
+                match import_heightmap(&path) {
+                    Ok(imported) => {
+                        commands.insert_resource(imported);
+                        *import_error = None;
+                    }
+                    Err(error) => {
+                        println!("Failed to import {}: {error}", path.display());
+                        *import_error = Some(error.to_string());
+                    }
+                }
+                *file_dialog = None;
+            }
+        }
+    }
+}
+
+fn init_presets() {
+    if let Err(error) = initalize_presets() {
+        println!("Failed to write the built in presets: {error}");
+    }
+}
+
+fn presets_ui(
+    mut contexts: EguiContexts,
+    mut world_settings: ResMut<WorldSettings>,
+    mut presets: Local<Option<Vec<WorldPreset>>>,
+    mut preset_name: Local<String>,
+    mut preset_status: Local<Option<String>>,
+) {
+    //Reread after saving, so the new preset shows up
+    if presets.is_none() {
+        *presets = Some(list_presets().unwrap_or_else(|error| {
+            println!("Failed to list presets: {error}");
+            builtin_presets()
+        }));
+    }
+    let ctx = contexts.ctx_mut();
+    egui::TopBottomPanel::top("World_Presets").show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("Preset")
+                .selected_text("Load Preset")
+                .show_ui(ui, |ui| {
+                    for preset in presets.iter().flatten() {
+                        if ui.selectable_label(false, &preset.name).clicked() {
+                            apply_preset(&mut world_settings, &preset.settings);
+                            preset_name.clone_from(&preset.name);
+                            *preset_status = Some(format!("Loaded {}", preset.name));
+                        }
+                    }
+                });
+            ui.add(
+                egui::TextEdit::singleline(&mut *preset_name)
+                    .hint_text("Preset name")
+                    .desired_width(150.0),
+            );
+            if ui.button("Save Preset").clicked() {
+                *preset_status = Some(match save_preset(&preset_name, &world_settings) {
+                    Ok(()) => {
+                        *presets = None;
+                        format!("Saved preset {}", *preset_name)
+                    }
+                    Err(error) => error.to_string(),
+                });
+            }
+            if let Some(preset_status) = preset_status.as_ref() {
+                ui.label(preset_status);
+            }
+        });
+    });
 }
 
 fn display_ui(
@@ -208,11 +583,16 @@ fn display_ui(
     mut egui_heightmap_image_handle: Local<Option<TextureId>>,
     mut world_settings: ResMut<WorldSettings>,
     mut seed_string: Local<String>,
-    heightmap_load_bar: Res<HeightmapLoadBar>,
+    mut displayed_seed: Local<Option<u32>>,
+    mut pipeline: ResMut<WorldGenPipeline>,
+    mut world_heightmap: ResMut<Heightmap>,
     mut game_state: ResMut<NextState<GameState>>,
     mut save_event: EventWriter<SaveEvent>,
+    mut save_finished: EventReader<SaveFinished>,
+    mut save_status: Local<Option<String>>,
     mut file_dialog: Local<Option<FileDialog>>,
     mut frame_counter: Local<u8>,
+    mut overlay: ResMut<PreviewOverlay>,
 ) {
     *frame_counter = frame_counter.saturating_add(1);
     if egui_heightmap_image_handle.is_none() {
@@ -220,8 +600,17 @@ fn display_ui(
         *egui_heightmap_image_handle = Some(heightmap_egui_handle);
     }
 
-    if seed_string.is_empty() {
+    for finished in save_finished.read() {
+        *save_status = Some(match &finished.result {
+            Ok(()) => format!("Saved to {}", finished.path.display()),
+            Err(error) => error.to_string(),
+        });
+    }
+
+    //The seed can also be changed by loading a preset
+    if *displayed_seed != Some(world_settings.noise_settings.seed) {
         *seed_string = world_settings.noise_settings.seed.to_string();
+        *displayed_seed = Some(world_settings.noise_settings.seed);
     }
     let ctx = contexts.ctx_mut();
 
@@ -249,15 +638,52 @@ fn display_ui(
                     }
                     ui.end_row();
 
+                    ui.label("Map Size");
+                    egui::ComboBox::from_id_source("Map_Size")
+                        .selected_text(world_settings.map_size.name())
+                        .show_ui(ui, |ui| {
+                            for map_size in MapSize::PRESETS {
+                                ui.selectable_value(
+                                    &mut world_settings.map_size,
+                                    map_size,
+                                    map_size.name(),
+                                );
+                            }
+                            let custom = MapSize::Custom(world_settings.world_size());
+                            ui.selectable_value(
+                                &mut world_settings.map_size,
+                                custom,
+                                custom.name(),
+                            );
+                        });
+                    ui.end_row();
+
+                    if let MapSize::Custom(chunks) = &mut world_settings.map_size {
+                        ui.label("Chunks");
+                        ui.horizontal(|ui| {
+                            for chunk in chunks {
+                                ui.add(
+                                    egui::DragValue::new(chunk)
+                                        .clamp_range(1..=MAX_CHUNK_WORLD_SIZE),
+                                );
+                            }
+                        });
+                        ui.end_row();
+                    }
+
+                    //Custom layers don't use these, they only drive the default layers
+                    let default_layers = world_settings.custom_noise_layers.is_none();
                     ui.label("Hilliness");
-                    ui.add(
+                    ui.add_enabled(
+                        default_layers,
                         egui::Slider::new(&mut world_settings.noise_settings.hilliness, 0.0..=1.0)
                             .clamp_to_range(true),
                     );
                     ui.end_row();
 
                     ui.label("Mountain Amount");
-                    ui.add(
+                    ui.add_enabled(
+                        default_layers,
                         egui::Slider::new(
                             &mut world_settings.noise_settings.mountain_amount,
                             0..=10,
@@ -267,7 +693,8 @@ fn display_ui(
                     ui.end_row();
 
                     ui.label("Mountain Size");
-                    ui.add(
+                    ui.add_enabled(
+                        default_layers,
                         egui::Slider::new(
                             &mut world_settings.noise_settings.mountain_size,
                             50.0..=200.0,
@@ -283,6 +710,29 @@ fn display_ui(
                     );
                     ui.end_row();
 
+                    ui.label("Thermal Erosion");
+                    ui.add(
+                        egui::Slider::new(&mut world_settings.thermal_erosion.iterations, 0..=100)
+                            .clamp_to_range(true),
+                    );
+                    ui.end_row();
+
+                    ui.label("Talus Angle");
+                    ui.add_enabled(
+                        world_settings.thermal_erosion.enabled(),
+                        egui::Slider::new(&mut world_settings.thermal_erosion.talus_angle, 10..=60)
+                            .suffix("°")
+                            .clamp_to_range(true),
+                    );
+                    ui.end_row();
+
+                    ui.label("Rivers");
+                    ui.add(
+                        egui::Slider::new(&mut world_settings.river_amount, 0..=50)
+                            .clamp_to_range(true),
+                    );
+                    ui.end_row();
+
                     ui.label("Sea Level");
                     ui.add(
                         egui::Slider::new(
@@ -292,8 +742,24 @@ fn display_ui(
                         .clamp_to_range(true),
                     );
                     ui.end_row();
+
+                    ui.label("Coastline");
+                    egui::ComboBox::from_id_source("Continent_Shape")
+                        .selected_text(world_settings.noise_settings.continent_shape.name())
+                        .show_ui(ui, |ui| {
+                            for shape in ContinentShape::iter() {
+                                ui.selectable_value(
+                                    &mut world_settings.noise_settings.continent_shape,
+                                    shape,
+                                    shape.name(),
+                                );
+                            }
+                        });
+                    ui.end_row();
                 });
-            if heightmap_load_bar.progress() >= 1.0 {
+            noise_layers_ui(ui, &mut world_settings);
+            erosion_settings_ui(ui, &mut world_settings.erosion);
+            if pipeline.is_finished() {
                 ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
                     let button = egui::Button::new("Save Heightmap").min_size([150.0, 65.0].into());
                     if ui.add(button).clicked() {
@@ -326,9 +792,13 @@ fn display_ui(
                                 path.set_extension("save");
                                 let event = SaveEvent(path);
                                 save_event.send(event);
+                                *file_dialog = None;
                             }
                         }
                     }
+                    if let Some(save_status) = save_status.as_ref() {
+                        ui.label(save_status);
+                    }
                 });
             }
         });
@@ -336,23 +806,50 @@ fn display_ui(
         .resizable(false)
         .show(contexts.ctx_mut(), |ui| {
             ui.with_layout(egui::Layout::top_down(egui::Align::Center), |ui| {
-                if heightmap_load_bar.heightmap_progress >= 1.0 {
+                if pipeline.stage() > GenerationStage::Noise {
                     let heightmap_image = egui::Image::new(egui::load::SizedTexture::new(
                         egui_heightmap_image_handle.unwrap(),
                         <[f32; 2] as Into<egui::Vec2>>::into(heightmap.size.to_array().as_f32()),
                     ))
                     .fit_to_exact_size([512.0, 512.0].into());
                     ui.add(heightmap_image);
+                    egui::ComboBox::from_label("Overlay")
+                        .selected_text(overlay.name())
+                        .show_ui(ui, |ui| {
+                            for option in PreviewOverlay::ALL {
+                                ui.selectable_value(&mut *overlay, option, option.name());
+                            }
+                        });
                 }
-                if heightmap_load_bar.progress() < 1.0 {
-                    let mut load_bar =
-                        egui::ProgressBar::new(heightmap_load_bar.progress()).desired_width(512.0);
-                    if heightmap_load_bar.heightmap_progress < 1.0 {
-                        load_bar = load_bar.text("Generating Heightmap");
-                    } else if heightmap_load_bar.erosion_progress < 1.0 {
-                        load_bar = load_bar.text("Eroding Heightmap");
+                if pipeline.is_cancelled() {
+                    ui.label(format!("{} cancelled", pipeline.stage().description()));
+                    //Changed settings can't reuse the stages that already finished
+                    let settings_changed = *pipeline.settings() != *world_settings;
+                    let label = if settings_changed {
+                        "Generate"
+                    } else {
+                        "Resume"
+                    };
+                    let button = egui::Button::new(label).min_size([150.0, 65.0].into());
+                    if ui.add(button).clicked() {
+                        if settings_changed {
+                            pipeline.restart(world_settings.clone());
+                        } else {
+                            pipeline.resume();
+                        }
                     }
+                } else if !pipeline.is_finished() {
+                    let load_bar = egui::ProgressBar::new(pipeline.progress())
+                        .desired_width(512.0)
+                        .text(format!(
+                            "{} {:.0}%",
+                            pipeline.stage().description(),
+                            pipeline.stage_progress() * 100.0
+                        ));
                     ui.add(load_bar);
+                    if ui.button("Cancel").clicked() {
+                        pipeline.cancel(&mut world_heightmap);
+                    }
                 } else {
                     ui.centered_and_justified(|ui| {
                         let button = egui::Button::new("New Game").min_size([150.0, 65.0].into());
@@ -368,3 +865,203 @@ fn display_ui(
         coz::progress!("Display UI");
     }
 }
+
+//The erosion parameters that aren't on the main settings grid
+fn erosion_settings_ui(ui: &mut egui::Ui, settings: &mut ErosionSettings) {
+    egui::CollapsingHeader::new("Advanced Erosion").show(ui, |ui| {
+        egui::Grid::new("Erosion_Settings")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Erosion Speed");
+                ui.add(
+                    egui::Slider::new(&mut settings.erosion_speed, 0.0..=1.0).clamp_to_range(true),
+                );
+                ui.end_row();
+
+                ui.label("Deposition Speed");
+                ui.add(
+                    egui::Slider::new(&mut settings.deposition_speed, 0.0..=1.0)
+                        .clamp_to_range(true),
+                );
+                ui.end_row();
+
+                ui.label("Gravity");
+                ui.add(egui::Slider::new(&mut settings.gravity, 1.0..=50.0).clamp_to_range(true));
+                ui.end_row();
+
+                ui.label("Direction Inertia");
+                ui.add(
+                    egui::Slider::new(&mut settings.direction_inertia, 0.0..=10.0)
+                        .clamp_to_range(true),
+                );
+                ui.end_row();
+
+                ui.label("Minimum Slope");
+                ui.add(
+                    egui::Slider::new(&mut settings.minimum_slope, 0.0..=0.1).clamp_to_range(true),
+                );
+                ui.end_row();
+
+                ui.label("Carry Capacity");
+                ui.add(
+                    egui::Slider::new(&mut settings.carry_capacity_modifier, 0.1..=5.0)
+                        .clamp_to_range(true),
+                );
+                ui.end_row();
+
+                ui.label("Evaporation Speed");
+                ui.add(
+                    egui::Slider::new(&mut settings.water_evaporation_speed, 0.0..=0.01)
+                        .logarithmic(true)
+                        .clamp_to_range(true),
+                );
+                ui.end_row();
+
+                //The largest droplets can't be smaller than the smallest ones
+                ui.label("Droplet Size");
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut settings.min_droplet_size).clamp_range(1..=24),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut settings.max_droplet_size)
+                            .clamp_range(settings.min_droplet_size..=24),
+                    );
+                });
+                ui.end_row();
+            });
+        if ui.button("Reset").clicked() {
+            *settings = ErosionSettings::default();
+        }
+    });
+}
+
+//Editor for the layer stack, layers are applied from the top of the list down
+fn noise_layers_ui(ui: &mut egui::Ui, world_settings: &mut WorldSettings) {
+    egui::CollapsingHeader::new("Noise Layers").show(ui, |ui| {
+        let mut custom = world_settings.custom_noise_layers.is_some();
+        if ui.checkbox(&mut custom, "Custom Layers").changed() {
+            //Start from the default layers so the terrain doesn't jump
+            world_settings.custom_noise_layers =
+                custom.then(|| default_layers(&world_settings.noise_settings));
+        }
+        let Some(layers) = &mut world_settings.custom_noise_layers else {
+            return;
+        };
+
+        let mut removed = None;
+        let mut moved_up = None;
+        egui::ScrollArea::vertical()
+            .max_height(400.0)
+            .show(ui, |ui| {
+                for (index, layer) in layers.iter_mut().enumerate() {
+                    ui.push_id(index, |ui| {
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.strong(format!("Layer {}", index + 1));
+                            if ui.add_enabled(index > 0, egui::Button::new("Up")).clicked() {
+                                moved_up = Some(index);
+                            }
+                            if ui.button("Remove").clicked() {
+                                removed = Some(index);
+                            }
+                        });
+                        noise_layer_ui(ui, layer);
+                    });
+                }
+            });
+        if let Some(index) = moved_up {
+            layers.swap(index - 1, index);
+        }
+        if let Some(index) = removed {
+            layers.remove(index);
+        }
+        if ui.button("Add Layer").clicked() {
+            layers.push(NoiseLayer::default());
+        }
+    });
+}
+
+fn noise_layer_ui(ui: &mut egui::Ui, layer: &mut NoiseLayer) {
+    egui::Grid::new("Noise_Layer")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Generator");
+            egui::ComboBox::from_id_source("Generator")
+                .selected_text(layer.generator.name())
+                .show_ui(ui, |ui| {
+                    for generator in LayerGenerator::iter() {
+                        ui.selectable_value(&mut layer.generator, generator, generator.name());
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Blend");
+            egui::ComboBox::from_id_source("Blend")
+                .selected_text(layer.blend.name())
+                .show_ui(ui, |ui| {
+                    for blend in LayerBlend::iter() {
+                        ui.selectable_value(&mut layer.blend, blend, blend.name());
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Scale");
+            ui.add(
+                egui::DragValue::new(&mut layer.scale)
+                    .speed(0.0001)
+                    .clamp_range(0.00001..=1.0),
+            );
+            ui.end_row();
+
+            ui.label("Amplitude");
+            ui.add(egui::DragValue::new(&mut layer.amplitude).speed(0.01));
+            ui.end_row();
+
+            ui.label("Bias");
+            ui.add(egui::DragValue::new(&mut layer.bias).speed(0.01));
+            ui.end_row();
+
+            ui.label("Octaves");
+            ui.add(egui::DragValue::new(&mut layer.octaves).clamp_range(1..=16));
+            ui.end_row();
+
+            ui.label("Seed Offset");
+            ui.add(egui::DragValue::new(&mut layer.seed_offset));
+            ui.end_row();
+
+            ui.label("Mask");
+            egui::ComboBox::from_id_source("Mask")
+                .selected_text(layer.mask.name())
+                .show_ui(ui, |ui| {
+                    for mask in LayerMask::OPTIONS {
+                        let selected = layer.mask.name() == mask.name();
+                        if ui.selectable_label(selected, mask.name()).clicked() && !selected {
+                            layer.mask = mask;
+                        }
+                    }
+                });
+            ui.end_row();
+
+            match &mut layer.mask {
+                LayerMask::None => {}
+                LayerMask::Circles {
+                    amount,
+                    size,
+                    std_dev,
+                } => {
+                    ui.label("Circles");
+                    ui.add(egui::Slider::new(amount, 0..=20));
+                    ui.end_row();
+
+                    ui.label("Circle Size");
+                    ui.add(egui::Slider::new(size, 10.0..=500.0));
+                    ui.end_row();
+
+                    ui.label("Falloff");
+                    ui.add(egui::Slider::new(std_dev, 0.1..=3.0));
+                    ui.end_row();
+                }
+            }
+        });
+}
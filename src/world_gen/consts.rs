@@ -2,11 +2,10 @@ use crate::world::WorldSize;
 
 pub const CHUNK_SIZE: u32 = 128;
 pub const HEIGHTMAP_CHUNK_SIZE: u32 = CHUNK_SIZE + 1;
-pub const CHUNK_WORLD_SIZE: WorldSize = [16, 16];
-pub const TILE_WORLD_SIZE: WorldSize = [
-    CHUNK_WORLD_SIZE[0] * CHUNK_SIZE,
-    CHUNK_WORLD_SIZE[1] * CHUNK_SIZE,
-];
+pub const SMALL_CHUNK_WORLD_SIZE: WorldSize = [8, 8];
+pub const MEDIUM_CHUNK_WORLD_SIZE: WorldSize = [16, 16];
+pub const LARGE_CHUNK_WORLD_SIZE: WorldSize = [24, 24];
+pub const MAX_CHUNK_WORLD_SIZE: u32 = 32;
 pub const TILE_SIZE: f32 = 1.0;
 pub const WORLD_HEIGHT_SCALE: f32 = 300.0;
 
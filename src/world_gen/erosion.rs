@@ -12,26 +12,28 @@ use bevy_app_compute::prelude::{
 use bytemuck::NoUninit;
 
 use itertools::Itertools;
+use ordered_float::NotNan;
 
 use rand::{rngs::StdRng, SeedableRng};
 use rand_distr::{Distribution, Uniform};
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
 use crate::utils::blur::{BlurComputeWorker, BlurShader, BlurWorkerFields, BLUR_WORKGROUP_SIZE};
 
+use self::thermal_erosion::{
+    ThermalErosionComputeWorker, ThermalErosionFields, ThermalErosionSettings,
+    ThermalErosionShader, THERMAL_WORKGROUP_SIZE,
+};
+
 use super::{
-    consts::{
-        CHUNK_WORLD_SIZE, EROSION_DISPATCH_SIZE, EROSION_WORKGROUP_SIZE, MAX_DROPLET_SIZE,
-        MIN_DROPLET_SIZE,
-    },
+    consts::{EROSION_DISPATCH_SIZE, EROSION_WORKGROUP_SIZE, MAX_DROPLET_SIZE, MIN_DROPLET_SIZE},
     heightmap::Heightmap,
-    HeightmapLoadBar, WorldSettings, HEIGHTMAP_CHUNK_SIZE,
+    pipeline::{GenerationStage, StageStatus, WorldGenPipeline},
 };
 
-use std::time::Instant;
-
-#[derive(Event)]
-pub struct ErosionEvent;
+pub mod cpu_erosion;
+pub mod thermal_erosion;
 
 #[derive(Debug, Clone, Copy, ShaderType, Default, NoUninit)]
 #[repr(C)]
@@ -46,6 +48,62 @@ pub struct Droplet {
     direction_y: f32,
 }
 
+//Tuning for the droplet erosion, the defaults are what the erosion used before it was configurable
+#[derive(Debug, Clone, Copy, ShaderType, NoUninit, Serialize, Deserialize)]
+#[repr(C)]
+#[serde(default)]
+pub struct ErosionSettings {
+    pub erosion_speed: f32,
+    pub gravity: f32,
+    pub deposition_speed: f32,
+    pub water_evaporation_speed: f32,
+    pub minimum_slope: f32,
+    pub direction_inertia: f32,
+    pub carry_capacity_modifier: f32,
+    //Only used on the cpu, when the droplets are generated
+    pub min_droplet_size: u32,
+    pub max_droplet_size: u32,
+}
+
+impl Default for ErosionSettings {
+    fn default() -> Self {
+        Self {
+            erosion_speed: 0.2,
+            gravity: 20.0,
+            deposition_speed: 0.2,
+            water_evaporation_speed: 0.0001,
+            minimum_slope: 0.01,
+            direction_inertia: 3.0,
+            carry_capacity_modifier: 1.0,
+            min_droplet_size: MIN_DROPLET_SIZE,
+            max_droplet_size: MAX_DROPLET_SIZE,
+        }
+    }
+}
+
+impl ErosionSettings {
+    fn floats(&self) -> [f32; 7] {
+        [
+            self.erosion_speed,
+            self.gravity,
+            self.deposition_speed,
+            self.water_evaporation_speed,
+            self.minimum_slope,
+            self.direction_inertia,
+            self.carry_capacity_modifier,
+        ]
+    }
+}
+
+impl PartialEq for ErosionSettings {
+    fn eq(&self, other: &Self) -> bool {
+        self.floats().map(NotNan::new) == other.floats().map(NotNan::new)
+            && self.min_droplet_size == other.min_droplet_size
+            && self.max_droplet_size == other.max_droplet_size
+    }
+}
+impl Eq for ErosionSettings {}
+
 #[derive(TypePath)]
 struct ErosionShader;
 
@@ -65,6 +123,8 @@ pub struct ErosionComputeWorker;
 pub enum ErosionComputeFields {
     Droplets,
     Results,
+    ImageSize,
+    Settings,
 }
 
 impl ComputeWorker for ErosionComputeWorker {
@@ -78,19 +138,19 @@ impl ComputeWorker for ErosionComputeWorker {
                     EROSION_DISPATCH_SIZE as usize * EROSION_WORKGROUP_SIZE as usize
                 ],
             )
-            .add_staging(
-                Self::Fields::Results,
-                &vec![
-                    0.0f32;
-                    (CHUNK_WORLD_SIZE[0]
-                        * HEIGHTMAP_CHUNK_SIZE as u32
-                        * CHUNK_WORLD_SIZE[1]
-                        * HEIGHTMAP_CHUNK_SIZE as u32) as usize
-                ],
-            )
+            //The heightmap buffers are sized when erosion starts, since the map size can change
+            .add_empty_staging(Self::Fields::Results, 0)
+            .add_empty_storage(Self::Fields::ImageSize, 0)
+            //Written when erosion starts
+            .add_uniform(Self::Fields::Settings, &ErosionSettings::default())
             .add_pass::<ErosionShader>(
                 [EROSION_DISPATCH_SIZE as u32, 1, 1],
-                &[Self::Fields::Droplets, Self::Fields::Results],
+                &[
+                    Self::Fields::Droplets,
+                    Self::Fields::Results,
+                    Self::Fields::ImageSize,
+                    Self::Fields::Settings,
+                ],
             )
             .one_shot()
             .set_wait_mode(false)
@@ -98,122 +158,209 @@ impl ComputeWorker for ErosionComputeWorker {
     }
 }
 
+pub fn generate_droplets(
+    rng: &mut StdRng,
+    map_size: [u32; 2],
+    settings: &ErosionSettings,
+) -> Vec<Droplet> {
+    let erosion_chunk_size = EROSION_DISPATCH_SIZE * EROSION_WORKGROUP_SIZE;
+    let x_sampler = Uniform::new(0, map_size[0]);
+    let y_sampler = Uniform::new(0, map_size[1]);
+    let radius_sampler = Uniform::new_inclusive(
+        settings.min_droplet_size,
+        settings.max_droplet_size.max(settings.min_droplet_size),
+    );
+    let direction_sampler = Uniform::new_inclusive(0, 1);
+    (0..erosion_chunk_size)
+        .map(|_| Droplet {
+            position_x: x_sampler.sample(rng),
+            position_y: y_sampler.sample(rng),
+            radius: radius_sampler.sample(rng),
+            sediment: 0.0,
+            water: 1.0,
+            speed: 0.0,
+            direction_x: direction_sampler.sample(rng) as f32,
+            direction_y: direction_sampler.sample(rng) as f32,
+        })
+        .collect_vec()
+}
+
+//The compute workers are only built when the render device is available, otherwise erosion falls back to the cpu
+pub fn gpu_erosion_available(
+    erosion_worker: Option<Res<AppComputeWorker<ErosionComputeWorker>>>,
+    blur_worker: Option<Res<AppComputeWorker<BlurComputeWorker>>>,
+    thermal_worker: Option<Res<AppComputeWorker<ThermalErosionComputeWorker>>>,
+    render_device: Option<Res<RenderDevice>>,
+) -> bool {
+    erosion_worker.is_some()
+        && blur_worker.is_some()
+        && thermal_worker.is_some()
+        && render_device.is_some()
+}
+
+//Blurs the heightmap to smooth out the erosion, returns None if the blur worker fails
+fn gpu_blur_eroded(
+    blur_worker: &mut AppComputeWorker<BlurComputeWorker>,
+    data: &[f32],
+    size: [u32; 2],
+    pipeline_cache: &AppPipelineCache,
+    render_device: &RenderDevice,
+) -> Option<Vec<f32>> {
+    blur_worker.add_staging(render_device, BlurWorkerFields::Image, data);
+    blur_worker.add_storage(render_device, BlurWorkerFields::ImageSize, &size);
+    blur_worker.add_storage(render_device, BlurWorkerFields::BlurSize, &[3u32, 3u32]);
+    blur_worker.set_dispatch_size::<BlurShader>([
+        (size[0] as f32 / BLUR_WORKGROUP_SIZE as f32).ceil() as u32,
+        (size[1] as f32 / BLUR_WORKGROUP_SIZE as f32).ceil() as u32,
+        1,
+    ]);
+
+    for _ in 0..2 {
+        if !blur_worker.execute_now(pipeline_cache) {
+            println!("Failed to execute blur worker");
+            return None;
+        }
+    }
+
+    Some(blur_worker.read_vec(BlurWorkerFields::Image))
+}
+
+//Same as thermal_erosion::thermal_erode, returns None if the thermal erosion worker fails
+fn gpu_thermal_erode(
+    thermal_worker: &mut AppComputeWorker<ThermalErosionComputeWorker>,
+    data: &[f32],
+    size: [u32; 2],
+    settings: &ThermalErosionSettings,
+    pipeline_cache: &AppPipelineCache,
+    render_device: &RenderDevice,
+) -> Option<Vec<f32>> {
+    thermal_worker.add_staging(render_device, ThermalErosionFields::Heights, data);
+    thermal_worker.add_staging(render_device, ThermalErosionFields::Results, data);
+    thermal_worker.add_storage(render_device, ThermalErosionFields::ImageSize, &size);
+    thermal_worker.add_storage(
+        render_device,
+        ThermalErosionFields::TalusHeight,
+        &[settings.talus_height()],
+    );
+    thermal_worker.set_dispatch_size::<ThermalErosionShader>([
+        size[0].div_ceil(THERMAL_WORKGROUP_SIZE),
+        size[1].div_ceil(THERMAL_WORKGROUP_SIZE),
+        1,
+    ]);
+
+    for _ in 0..settings.iterations {
+        if !thermal_worker.execute_now(pipeline_cache) {
+            println!("Failed to execute thermal erosion worker");
+            return None;
+        }
+    }
+
+    Some(thermal_worker.read_vec(ThermalErosionFields::Heights))
+}
+
 pub fn gpu_erode_heightmap(
     mut erosion_worker: ResMut<AppComputeWorker<ErosionComputeWorker>>,
-    settings: Res<WorldSettings>,
     mut heightmap: ResMut<Heightmap>,
-    mut heightmap_load_bar: ResMut<HeightmapLoadBar>,
+    mut pipeline: ResMut<WorldGenPipeline>,
     mut erosion_counter: Local<u32>,
-    mut erosion_event: EventReader<ErosionEvent>,
-    mut working: Local<bool>,
-    mut benchmark: Local<Option<Instant>>,
+    mut run: Local<Option<u32>>,
     mut rng: Local<Option<StdRng>>,
-    mut blur_worker: ResMut<AppComputeWorker<BlurComputeWorker>>,
-    pipeline_cache: Res<AppPipelineCache>,
     render_device: Res<RenderDevice>,
 ) {
-    let erosion_chunks = settings.erosion_amount;
-    let erosion_chunk_size = EROSION_DISPATCH_SIZE * EROSION_WORKGROUP_SIZE;
+    let erosion_chunks = pipeline.settings().erosion_amount;
+    let erosion_settings = pipeline.settings().erosion;
 
-    if erosion_event.read().count() > 0 {
-        *erosion_counter = erosion_chunks;
-        heightmap_load_bar.erosion_progress = 0.0;
-        *working = true;
-        *benchmark = Some(Instant::now());
-        *rng = Some(StdRng::seed_from_u64(settings.noise_settings.seed as u64));
-
-        let map_size = [
-            (CHUNK_WORLD_SIZE[0] * HEIGHTMAP_CHUNK_SIZE),
-            (CHUNK_WORLD_SIZE[1] * HEIGHTMAP_CHUNK_SIZE),
-        ];
-
-        let position_sampler = Uniform::new(0, map_size[0]);
-        let radius_sampler = Uniform::new_inclusive(MIN_DROPLET_SIZE, MAX_DROPLET_SIZE);
-        let direction_sampler = Uniform::new_inclusive(0, 1);
-        let rng = rng.as_mut().unwrap();
-        let droplets = (0..erosion_chunk_size)
-            .map(|_| Droplet {
-                position_x: position_sampler.sample(rng),
-                position_y: position_sampler.sample(rng),
-                radius: radius_sampler.sample(rng),
-                sediment: 0.0,
-                water: 1.0,
-                speed: 0.0,
-                direction_x: direction_sampler.sample(rng) as f32,
-                direction_y: direction_sampler.sample(rng) as f32,
-            })
-            .collect_vec();
-
-        erosion_worker.write_slice(ErosionComputeFields::Results, heightmap.data.as_slice());
-        erosion_worker.write_slice(ErosionComputeFields::Droplets, droplets.as_slice());
+    match pipeline.status(GenerationStage::Erosion, &mut run) {
+        //Work already sent to the gpu can't be stopped, it is just never read back
+        StageStatus::Inactive => return,
+        StageStatus::Started => {
+            *erosion_counter = erosion_chunks;
+            *rng = Some(StdRng::seed_from_u64(pipeline.settings().seed() as u64));
 
-        erosion_worker.execute();
-    }
-    if *working {
-        if *erosion_counter == 0 && erosion_worker.ready() {
-            //This will read from the work done the previous frame
-            let heightmap_floats: Vec<f32> = erosion_worker.read_vec(ErosionComputeFields::Results);
-            //Blur the heightmap to smooth out the erosion
-            blur_worker.add_staging(&render_device, BlurWorkerFields::Image, &heightmap_floats);
-            blur_worker.add_storage(
+            let droplets = generate_droplets(
+                rng.as_mut().unwrap(),
+                heightmap.shader_size(),
+                &erosion_settings,
+            );
+
+            erosion_worker.add_staging(
                 &render_device,
-                BlurWorkerFields::ImageSize,
-                &heightmap.size(),
+                ErosionComputeFields::Results,
+                heightmap.data.as_slice(),
             );
-            blur_worker.add_storage(&render_device, BlurWorkerFields::BlurSize, &[3u32, 3u32]);
-            blur_worker.set_dispatch_size::<BlurShader>([
-                (heightmap.size()[0] as f32 / BLUR_WORKGROUP_SIZE as f32).ceil() as u32,
-                (heightmap.size()[1] as f32 / BLUR_WORKGROUP_SIZE as f32).ceil() as u32,
-                1,
-            ]);
-
-            for _ in 0..2 {
-                if !blur_worker.execute_now(&pipeline_cache) {
-                    println!("Failed to execute blur worker");
-                    return;
-                }
-            }
-
-            let blur_result: Vec<f32> = blur_worker.read_vec(BlurWorkerFields::Image);
-            heightmap.data = blur_result;
-
-            heightmap_load_bar.erosion_progress = 1.0;
-            *working = false;
-            println!(
-                "Erosion took: {:?}",
-                Instant::now().duration_since(benchmark.unwrap())
+            erosion_worker.add_storage(
+                &render_device,
+                ErosionComputeFields::ImageSize,
+                &heightmap.shader_size(),
             );
-        } else if erosion_worker.ready() {
-            let map_size = [
-                (CHUNK_WORLD_SIZE[0] * HEIGHTMAP_CHUNK_SIZE),
-                (CHUNK_WORLD_SIZE[1] * HEIGHTMAP_CHUNK_SIZE),
-            ];
-
-            let position_sampler = Uniform::new(0, map_size[0]);
-            let radius_sampler = Uniform::new_inclusive(MIN_DROPLET_SIZE, MAX_DROPLET_SIZE);
-            let direction_sampler = Uniform::new_inclusive(0, 1);
-            let rng = rng.as_mut().unwrap();
-            let droplets = (0..erosion_chunk_size)
-                .map(|_| Droplet {
-                    position_x: position_sampler.sample(rng),
-                    position_y: position_sampler.sample(rng),
-                    radius: radius_sampler.sample(rng),
-                    sediment: 0.0,
-                    water: 1.0,
-                    speed: 0.0,
-                    direction_x: direction_sampler.sample(rng) as f32,
-                    direction_y: direction_sampler.sample(rng) as f32,
-                })
-                .collect_vec();
-
+            erosion_worker.write(ErosionComputeFields::Settings, &erosion_settings);
             erosion_worker.write_slice(ErosionComputeFields::Droplets, droplets.as_slice());
 
             erosion_worker.execute();
+        }
+        StageStatus::Running => {}
+    }
+    if !erosion_worker.ready() {
+        return;
+    }
+    if *erosion_counter == 0 {
+        //This will read from the work done the previous frame
+        heightmap.data = erosion_worker.read_vec(ErosionComputeFields::Results);
+        pipeline.advance(&heightmap);
+    } else {
+        let droplets = generate_droplets(
+            rng.as_mut().unwrap(),
+            heightmap.shader_size(),
+            &erosion_settings,
+        );
 
-            //This doesn't read from the gpu every frame, since we only need the result for updating the heightmap image, and at the end of the erosion process
+        erosion_worker.write_slice(ErosionComputeFields::Droplets, droplets.as_slice());
 
-            heightmap_load_bar.erosion_progress += 1.0 / erosion_chunks as f32;
-            *erosion_counter = erosion_counter.saturating_sub(1);
-        }
+        erosion_worker.execute();
+
+        //This doesn't read from the gpu every frame, since we only need the result for updating the heightmap image, and at the end of the erosion process
+
+        *erosion_counter = erosion_counter.saturating_sub(1);
+        pipeline.set_progress(1.0 - *erosion_counter as f32 / erosion_chunks as f32);
+    }
+}
+
+//Runs in a single frame, so there is nothing to cancel
+pub fn gpu_smooth_heightmap(
+    mut heightmap: ResMut<Heightmap>,
+    mut pipeline: ResMut<WorldGenPipeline>,
+    mut blur_worker: ResMut<AppComputeWorker<BlurComputeWorker>>,
+    mut thermal_worker: ResMut<AppComputeWorker<ThermalErosionComputeWorker>>,
+    pipeline_cache: Res<AppPipelineCache>,
+    render_device: Res<RenderDevice>,
+) {
+    if !pipeline.running(GenerationStage::Smoothing) {
+        return;
+    }
+    let size = heightmap.shader_size();
+    let thermal_erosion = pipeline.settings().thermal_erosion;
+    //Thermal erosion replaces the blur when it is turned on
+    let smoothed = if thermal_erosion.enabled() {
+        gpu_thermal_erode(
+            &mut thermal_worker,
+            &heightmap.data,
+            size,
+            &thermal_erosion,
+            &pipeline_cache,
+            &render_device,
+        )
+    } else {
+        gpu_blur_eroded(
+            &mut blur_worker,
+            &heightmap.data,
+            size,
+            &pipeline_cache,
+            &render_device,
+        )
+    };
+    //Tried again next frame if the workers failed
+    if let Some(smoothed) = smoothed {
+        heightmap.data = smoothed;
+        pipeline.advance(&heightmap);
     }
 }
@@ -0,0 +1,262 @@
+use bevy::{
+    prelude::*,
+    tasks::{block_on, AsyncComputeTaskPool, Task},
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{utils::blur::cpu_blur, world::WorldSize};
+
+use super::{
+    super::{
+        consts::MAX_EROSION_STEPS,
+        heightmap::Heightmap,
+        pipeline::{GenerationStage, StageStatus, WorldGenPipeline},
+        WorldSettings,
+    },
+    generate_droplets,
+    thermal_erosion::{thermal_erode, ThermalErosionSettings},
+    Droplet, ErosionSettings,
+};
+
+//The gpu dispatches one batch of droplets when erosion starts, and then one more for every erosion chunk
+pub fn erosion_batches(settings: &WorldSettings) -> u32 {
+    settings.erosion_amount + 1
+}
+
+//Erodes the whole heightmap on the current thread, used when there is no app to spread the work over frames
+pub fn erode_heightmap(heightmap: &mut Heightmap, settings: &WorldSettings) {
+    let mut rng = StdRng::seed_from_u64(u64::from(settings.noise_settings.seed));
+    let size = heightmap.shader_size();
+    let mut data = std::mem::take(&mut heightmap.data);
+    for _ in 0..erosion_batches(settings) {
+        let mut droplets = generate_droplets(&mut rng, size, &settings.erosion);
+        erode_batch(&mut data, size, &mut droplets, &settings.erosion);
+    }
+    heightmap.data = smooth_eroded(&data, size, &settings.thermal_erosion);
+}
+
+pub fn erode_batch(
+    data: &mut [f32],
+    size: WorldSize,
+    droplets: &mut [Droplet],
+    settings: &ErosionSettings,
+) {
+    for droplet in droplets {
+        erode_droplet(droplet, data, size, settings);
+    }
+}
+
+//Blurs the heightmap to smooth out the erosion, same as the gpu path
+pub fn blur_eroded(data: &[f32], size: WorldSize) -> Vec<f32> {
+    let mut data = data.to_vec();
+    for _ in 0..2 {
+        data = cpu_blur(&data, size, [3, 3]);
+    }
+    data
+}
+
+//Thermal erosion replaces the blur when it is turned on
+pub fn smooth_eroded(data: &[f32], size: WorldSize, thermal: &ThermalErosionSettings) -> Vec<f32> {
+    if thermal.enabled() {
+        thermal_erode(data, size, thermal)
+    } else {
+        blur_eroded(data, size)
+    }
+}
+
+pub fn cpu_erode_heightmap(
+    mut heightmap: ResMut<Heightmap>,
+    mut pipeline: ResMut<WorldGenPipeline>,
+    mut erosion_counter: Local<u32>,
+    mut task: Local<Option<Task<Vec<f32>>>>,
+    mut run: Local<Option<u32>>,
+    mut rng: Local<Option<StdRng>>,
+) {
+    let batches = erosion_batches(pipeline.settings());
+
+    match pipeline.status(GenerationStage::Erosion, &mut run) {
+        StageStatus::Inactive => {
+            //Dropping the task cancels it
+            *task = None;
+            return;
+        }
+        StageStatus::Started => {
+            *task = None;
+            *erosion_counter = batches;
+            *rng = Some(StdRng::seed_from_u64(u64::from(pipeline.settings().seed())));
+        }
+        StageStatus::Running => {}
+    }
+
+    if task.as_ref().is_some_and(Task::is_finished) {
+        heightmap.data = block_on(task.take().unwrap());
+        *erosion_counter = erosion_counter.saturating_sub(1);
+        pipeline.set_progress(1.0 - *erosion_counter as f32 / batches as f32);
+    }
+    if task.is_some() {
+        return;
+    }
+
+    if *erosion_counter == 0 {
+        pipeline.advance(&heightmap);
+    } else {
+        let size = heightmap.shader_size();
+        let mut data = heightmap.data.clone();
+        let erosion_settings = pipeline.settings().erosion;
+        let mut droplets = generate_droplets(rng.as_mut().unwrap(), size, &erosion_settings);
+        *task = Some(AsyncComputeTaskPool::get().spawn(async move {
+            erode_batch(&mut data, size, &mut droplets, &erosion_settings);
+            data
+        }));
+    }
+}
+
+//Thermal erosion is too slow to run in a frame, so the smoothing runs in the background too
+pub fn cpu_smooth_heightmap(
+    mut heightmap: ResMut<Heightmap>,
+    mut pipeline: ResMut<WorldGenPipeline>,
+    mut task: Local<Option<Task<Vec<f32>>>>,
+    mut run: Local<Option<u32>>,
+) {
+    match pipeline.status(GenerationStage::Smoothing, &mut run) {
+        StageStatus::Inactive => {
+            //Dropping the task cancels it
+            *task = None;
+            return;
+        }
+        StageStatus::Started => {
+            let size = heightmap.shader_size();
+            let data = heightmap.data.clone();
+            let thermal_erosion = pipeline.settings().thermal_erosion;
+            *task = Some(
+                AsyncComputeTaskPool::get()
+                    .spawn(async move { smooth_eroded(&data, size, &thermal_erosion) }),
+            );
+        }
+        StageStatus::Running => {}
+    }
+    if task.as_ref().is_some_and(Task::is_finished) {
+        heightmap.data = block_on(task.take().unwrap());
+        pipeline.advance(&heightmap);
+    }
+}
+
+fn index(position: [i32; 2], size: WorldSize) -> usize {
+    (position[0] + position[1] * size[0] as i32) as usize
+}
+
+fn bound_check(position: [i32; 2], size: WorldSize) -> bool {
+    position[0] < size[0] as i32
+        && position[0] > 0
+        && position[1] < size[1] as i32
+        && position[1] > 0
+}
+
+//Port of the erosion function in terrain_erosion.wgsl
+fn erode_droplet(
+    droplet: &mut Droplet,
+    data: &mut [f32],
+    size: WorldSize,
+    settings: &ErosionSettings,
+) {
+    for _ in 0..MAX_EROSION_STEPS {
+        let position = [droplet.position_x as i32, droplet.position_y as i32];
+        let radius = droplet.radius as i32;
+        let position_f32 = Vec2::new(position[0] as f32, position[1] as f32);
+        //Get the neighbours with the current radius
+        for dx in -radius..=radius {
+            for dy in -radius..=radius {
+                let neighbour = [position[0] + dx, position[1] + dy];
+                let neighbour_f32 = Vec2::new(neighbour[0] as f32, neighbour[1] as f32);
+                if !bound_check(neighbour, size)
+                    || position_f32.distance(neighbour_f32) >= radius as f32
+                    || neighbour == position
+                {
+                    continue;
+                }
+                let height_difference = data[index(position, size)] - data[index(neighbour, size)];
+                let direction = (position_f32 - neighbour_f32)
+                    * (-height_difference)
+                    * settings.gravity
+                    * settings.direction_inertia;
+                droplet.direction_x += direction.x;
+                droplet.direction_y += direction.y;
+            }
+        }
+        let normalized_direction =
+            Vec2::new(droplet.direction_x, droplet.direction_y).normalize_or_zero();
+        droplet.direction_x = normalized_direction.x;
+        droplet.direction_y = normalized_direction.y;
+
+        let next_position = (position_f32 + normalized_direction * radius as f32).round();
+        let next_position = [
+            next_position.x.max(0.0) as i32,
+            next_position.y.max(0.0) as i32,
+        ];
+        if !bound_check(next_position, size) {
+            return;
+        }
+
+        droplet.water *= 1.0 - settings.water_evaporation_speed;
+
+        let height_difference = data[index(position, size)] - data[index(next_position, size)];
+        droplet.speed += height_difference * settings.gravity;
+        droplet.direction_x *= droplet.speed;
+        droplet.direction_y *= droplet.speed;
+
+        let carry_capacity = height_difference.max(settings.minimum_slope)
+            * droplet.speed
+            * droplet.water
+            * droplet.radius as f32
+            * settings.carry_capacity_modifier;
+
+        if droplet.water < 0.1 || droplet.speed < 0.0 {
+            droplet.radius *= 5;
+            deposit(
+                droplet,
+                data,
+                size,
+                droplet.sediment * settings.deposition_speed,
+            );
+            return;
+        } else if droplet.sediment > carry_capacity {
+            let deposit_amount = (droplet.sediment - carry_capacity) * settings.deposition_speed;
+            deposit(droplet, data, size, deposit_amount);
+        } else {
+            let sediment_delta = (carry_capacity - droplet.sediment) * settings.erosion_speed;
+            let erosion_amount = sediment_delta.min(height_difference);
+            deposit(droplet, data, size, -erosion_amount);
+        }
+        droplet.position_x = next_position[0] as u32;
+        droplet.position_y = next_position[1] as u32;
+    }
+    droplet.radius *= 5;
+    deposit(
+        droplet,
+        data,
+        size,
+        droplet.sediment * settings.deposition_speed,
+    );
+}
+
+fn deposit(droplet: &mut Droplet, data: &mut [f32], size: WorldSize, amount: f32) {
+    let radius = droplet.radius as i32;
+    let position = [droplet.position_x as i32, droplet.position_y as i32];
+    let position_f32 = Vec2::new(position[0] as f32, position[1] as f32);
+    let std_dev = radius as f32 * 0.5;
+    for dx in -radius..=radius {
+        for dy in -radius..=radius {
+            let neighbour = [position[0] + dx, position[1] + dy];
+            let distance =
+                position_f32.distance(Vec2::new(neighbour[0] as f32, neighbour[1] as f32));
+            if bound_check(neighbour, size) && distance < radius as f32 {
+                //Same normal curve as the shader, which differs slightly from utils::math::normal_curve
+                let curve = (1.0 / (std_dev * 2.0 * std::f32::consts::PI).sqrt())
+                    * (-0.5 * (distance / std_dev).powi(2)).exp();
+                let height = &mut data[index(neighbour, size)];
+                *height = (*height + amount * curve).clamp(0.0, 1.0);
+            }
+        }
+    }
+    droplet.sediment -= amount;
+}
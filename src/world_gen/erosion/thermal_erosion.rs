@@ -0,0 +1,214 @@
+use bevy::{prelude::*, render::render_resource::ShaderRef};
+use bevy_app_compute::prelude::{
+    AppComputeWorker, AppComputeWorkerBuilder, ComputeShader, ComputeWorker,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    world::WorldSize,
+    world_gen::consts::{TILE_SIZE, WORLD_HEIGHT_SCALE},
+};
+
+//Matches the constant in constants.wgsl
+pub const THERMAL_WORKGROUP_SIZE: u32 = 16;
+//Matches the constant in thermal_erosion.wgsl
+//Fraction of the material past the talus angle that slides down each pass
+const THERMAL_RATE: f32 = 0.5;
+
+//Slides material down slopes that are steeper than the talus angle, leaving scree slopes at the foot of cliffs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThermalErosionSettings {
+    //The hydraulic erosion is blurred instead when this is 0
+    pub iterations: u32,
+    //In degrees
+    pub talus_angle: u32,
+}
+
+impl Default for ThermalErosionSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 0,
+            talus_angle: 35,
+        }
+    }
+}
+
+impl ThermalErosionSettings {
+    pub fn enabled(&self) -> bool {
+        self.iterations > 0
+    }
+    //The steepest height difference between neighbouring points that doesn't crumble, in heightmap units
+    pub fn talus_height(&self) -> f32 {
+        (self.talus_angle as f32).to_radians().tan() * TILE_SIZE / WORLD_HEIGHT_SCALE
+    }
+}
+
+#[derive(TypePath)]
+pub struct ThermalErosionShader;
+
+impl ComputeShader for ThermalErosionShader {
+    fn shader() -> ShaderRef {
+        "shaders/thermal_erosion.wgsl".into()
+    }
+    fn dependencies() -> Vec<ShaderRef> {
+        vec!["shaders/constants.wgsl".into()]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ThermalErosionFields {
+    Heights,
+    Results,
+    ImageSize,
+    TalusHeight,
+}
+
+#[derive(Resource)]
+pub struct ThermalErosionComputeWorker;
+
+impl ComputeWorker for ThermalErosionComputeWorker {
+    type Fields = ThermalErosionFields;
+
+    //Each execution is one iteration, the second pass writes the result back into the heights
+    fn build(app: &mut App) -> AppComputeWorker<Self> {
+        AppComputeWorkerBuilder::new(app)
+            .add_empty_staging(Self::Fields::Heights, 0)
+            .add_empty_staging(Self::Fields::Results, 0)
+            .add_empty_storage(Self::Fields::ImageSize, 0)
+            .add_empty_storage(Self::Fields::TalusHeight, 0)
+            .add_pass::<ThermalErosionShader>(
+                [1, 1, 1],
+                &[
+                    Self::Fields::Heights,
+                    Self::Fields::Results,
+                    Self::Fields::ImageSize,
+                    Self::Fields::TalusHeight,
+                ],
+            )
+            .add_pass::<ThermalErosionShader>(
+                [1, 1, 1],
+                &[
+                    Self::Fields::Results,
+                    Self::Fields::Heights,
+                    Self::Fields::ImageSize,
+                    Self::Fields::TalusHeight,
+                ],
+            )
+            .immediate()
+            .build()
+    }
+}
+
+fn index([x, y]: [i32; 2], size: WorldSize) -> usize {
+    (x + y * size[0] as i32) as usize
+}
+
+fn neighbours([x, y]: [i32; 2], size: WorldSize) -> impl Iterator<Item = [i32; 2]> {
+    (-1..=1)
+        .flat_map(move |dx| (-1..=1).map(move |dy| [x + dx, y + dy]))
+        .filter(move |&neighbour| {
+            neighbour != [x, y]
+                && neighbour[0] >= 0
+                && neighbour[1] >= 0
+                && neighbour[0] < size[0] as i32
+                && neighbour[1] < size[1] as i32
+        })
+}
+
+//How far the drop to the neighbour goes past the talus angle, diagonal neighbours are further away
+fn excess(data: &[f32], size: WorldSize, point: [i32; 2], neighbour: [i32; 2], talus: f32) -> f32 {
+    let distance = if neighbour[0] != point[0] && neighbour[1] != point[1] {
+        std::f32::consts::SQRT_2
+    } else {
+        1.0
+    };
+    (data[index(point, size)] - data[index(neighbour, size)] - talus * distance).max(0.0)
+}
+
+//Cpu version of thermal_erosion.wgsl, runs the same two passes per iteration
+pub fn thermal_erode(data: &[f32], size: WorldSize, settings: &ThermalErosionSettings) -> Vec<f32> {
+    let mut data = data.to_vec();
+    for _ in 0..settings.iterations * 2 {
+        data = thermal_pass(&data, size, settings.talus_height());
+    }
+    data
+}
+
+//Every point sheds half of its steepest excess, split between its lower neighbours by how steep they are
+//Half of the steepest drop is where the two points would end up level, so it never overshoots
+fn thermal_pass(data: &[f32], size: WorldSize, talus: f32) -> Vec<f32> {
+    let mut result = data.to_vec();
+    for y in 0..size[1] as i32 {
+        for x in 0..size[0] as i32 {
+            let point = [x, y];
+            let excesses = neighbours(point, size)
+                .map(|neighbour| (neighbour, excess(data, size, point, neighbour, talus)))
+                .filter(|(_, excess)| *excess > 0.0)
+                .collect::<Vec<_>>();
+            let total = excesses.iter().map(|(_, excess)| excess).sum::<f32>();
+            let steepest = excesses
+                .iter()
+                .map(|(_, excess)| *excess)
+                .fold(0.0, f32::max);
+            if total <= 0.0 {
+                continue;
+            }
+            let moved = steepest * THERMAL_RATE * 0.5;
+            result[index(point, size)] -= moved;
+            for (neighbour, excess) in excesses {
+                result[index(neighbour, size)] += moved * excess / total;
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: WorldSize = [9, 9];
+
+    fn settings() -> ThermalErosionSettings {
+        ThermalErosionSettings {
+            iterations: 50,
+            talus_angle: 30,
+        }
+    }
+
+    fn steepest_drop(data: &[f32]) -> f32 {
+        let mut steepest: f32 = 0.0;
+        for y in 0..SIZE[1] as i32 {
+            for x in 0..SIZE[0] as i32 {
+                for neighbour in neighbours([x, y], SIZE) {
+                    if neighbour[0] == x || neighbour[1] == y {
+                        let drop = data[index([x, y], SIZE)] - data[index(neighbour, SIZE)];
+                        steepest = steepest.max(drop);
+                    }
+                }
+            }
+        }
+        steepest
+    }
+
+    #[test]
+    fn spikes_crumble_down_to_the_talus_angle() {
+        let mut data = vec![0.0; (SIZE[0] * SIZE[1]) as usize];
+        data[index([4, 4], SIZE)] = 0.1;
+        let eroded = thermal_erode(&data, SIZE, &settings());
+        let talus = settings().talus_height();
+        assert!(steepest_drop(&eroded) < talus * 1.01);
+        //Material is only moved, never removed
+        let total = eroded.iter().sum::<f32>();
+        assert!((total - 0.1).abs() < 1e-4);
+    }
+
+    #[test]
+    fn gentle_slopes_are_left_alone() {
+        let talus = settings().talus_height();
+        let data = (0..SIZE[1])
+            .flat_map(|_| (0..SIZE[0]).map(|x| x as f32 * talus * 0.5))
+            .collect::<Vec<_>>();
+        assert_eq!(thermal_erode(&data, SIZE, &settings()), data);
+    }
+}
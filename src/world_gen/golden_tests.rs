@@ -0,0 +1,150 @@
+//Checks that the same settings keep generating the same terrain, since players share seeds
+//Run with UPDATE_GOLDEN=1 to rewrite the golden files after an intentional change to world generation
+use std::{env, fs, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::headless::generate_heightmap;
+
+use super::{heightmap::Heightmap, noise_gen::NoiseSettings, MapSize, WorldSettings};
+
+//Heights are rounded to this before hashing, so tiny float differences between platforms don't fail the test
+const CHECKSUM_PRECISION: f32 = 1.0 / 4096.0;
+const STATISTIC_TOLERANCE: f64 = 1e-4;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct HeightmapStatistics {
+    size: [u32; 2],
+    checksum: u64,
+    min: f64,
+    max: f64,
+    mean: f64,
+    standard_deviation: f64,
+}
+
+impl HeightmapStatistics {
+    fn new(heightmap: &Heightmap) -> Self {
+        //FNV-1a, std's hasher isn't guaranteed to stay the same between releases
+        let mut checksum: u64 = 0xcbf2_9ce4_8422_2325;
+        for height in &heightmap.data {
+            let rounded = (height / CHECKSUM_PRECISION).round() as i64;
+            for byte in rounded.to_le_bytes() {
+                checksum ^= u64::from(byte);
+                checksum = checksum.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        let heights = heightmap.data.iter().map(|&height| f64::from(height));
+        let count = heightmap.data.len() as f64;
+        let mean = heights.clone().sum::<f64>() / count;
+        let variance = heights
+            .clone()
+            .map(|height| (height - mean).powi(2))
+            .sum::<f64>()
+            / count;
+        Self {
+            size: heightmap.size(),
+            checksum,
+            min: heights.clone().fold(f64::INFINITY, f64::min),
+            max: heights.fold(f64::NEG_INFINITY, f64::max),
+            mean,
+            standard_deviation: variance.sqrt(),
+        }
+    }
+
+    fn assert_matches(&self, golden: &Self, case: &str) {
+        assert_eq!(self.size, golden.size, "{case}: size changed");
+        for (name, value, golden_value) in [
+            ("min", self.min, golden.min),
+            ("max", self.max, golden.max),
+            ("mean", self.mean, golden.mean),
+            (
+                "standard deviation",
+                self.standard_deviation,
+                golden.standard_deviation,
+            ),
+        ] {
+            assert!(
+                (value - golden_value).abs() <= STATISTIC_TOLERANCE,
+                "{case}: {name} drifted from {golden_value} to {value}"
+            );
+        }
+        assert_eq!(self.checksum, golden.checksum, "{case}: checksum changed");
+    }
+}
+
+fn cases() -> Vec<(&'static str, WorldSettings)> {
+    let small_map = MapSize::Custom([2, 1]);
+    vec![
+        (
+            "default",
+            WorldSettings {
+                map_size: small_map,
+                ..Default::default()
+            },
+        ),
+        (
+            "mountains",
+            WorldSettings {
+                noise_settings: NoiseSettings {
+                    seed: 1234,
+                    mountain_amount: 5,
+                    mountain_size: 150.0,
+                    hilliness: 0.8,
+                    ..Default::default()
+                },
+                map_size: small_map,
+                ..Default::default()
+            },
+        ),
+        (
+            "eroded",
+            WorldSettings {
+                noise_settings: NoiseSettings {
+                    seed: 42,
+                    ..Default::default()
+                },
+                erosion_amount: 3,
+                map_size: small_map,
+                ..Default::default()
+            },
+        ),
+    ]
+}
+
+fn golden_path(case: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{case}.ron"))
+}
+
+#[test]
+fn world_generation_matches_golden_files() {
+    let update = env::var("UPDATE_GOLDEN").is_ok();
+    for (case, settings) in cases() {
+        let statistics = HeightmapStatistics::new(&generate_heightmap(&settings));
+        let path = golden_path(case);
+        if update {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let pretty = ron::ser::PrettyConfig::default();
+            fs::write(
+                &path,
+                ron::ser::to_string_pretty(&statistics, pretty).unwrap(),
+            )
+            .unwrap();
+            continue;
+        }
+        let golden = fs::read_to_string(&path)
+            .unwrap_or_else(|_| panic!("Missing {}, run with UPDATE_GOLDEN=1", path.display()));
+        statistics.assert_matches(&ron::from_str(&golden).unwrap(), case);
+    }
+}
+
+#[test]
+fn generation_is_repeatable() {
+    let (_, settings) = cases().remove(2);
+    assert_eq!(
+        generate_heightmap(&settings).data,
+        generate_heightmap(&settings).data
+    );
+}
@@ -12,8 +12,7 @@ use bevy::{
     prelude::*,
     render::{extract_resource::ExtractResource, render_asset::RenderAssetUsages},
 };
-use image::{DynamicImage, RgbaImage};
-use itertools::Itertools;
+use image::{DynamicImage, Rgba, RgbaImage};
 use num::Integer;
 use num_traits::AsPrimitive;
 use serde::{Deserialize, Serialize};
@@ -24,6 +23,11 @@ use super::{
     WorldSettings, HEIGHTMAP_CHUNK_SIZE,
 };
 
+pub mod edit;
+pub mod export;
+pub mod hydrology;
+pub mod import;
+
 #[derive(Resource, Clone, Debug, Serialize, Deserialize)]
 pub struct Heightmap {
     pub data: Vec<f32>,
@@ -67,6 +71,15 @@ impl Heightmap {
             ],
         }
     }
+    //Size is the amount of points, not chunks
+    pub fn from_raw(size: WorldSize, data: Vec<f32>) -> Self {
+        assert_eq!(
+            data.len(),
+            (size[0] * size[1]) as usize,
+            "Heightmap data doesn't match its size"
+        );
+        Self { data, size }
+    }
     pub fn get<N: Integer + AsPrimitive<usize>, T: Into<[N; 2]>>(&self, point: T) -> Option<f32> {
         let point = point.into();
         self.data
@@ -76,6 +89,46 @@ impl Heightmap {
     pub fn size(&self) -> WorldSize {
         [self.size[0], self.size[1]]
     }
+    //Bilinearly stretches the heightmap over a world of the given size in chunks
+    pub fn resampled(&self, world_size: WorldSize) -> Self {
+        let mut resampled = Self::new(world_size);
+        let [width, height] = self.size();
+        let [new_width, new_height] = resampled.size();
+        if width == 0 || height == 0 {
+            return resampled;
+        }
+        let scale = [
+            (width - 1) as f32 / (new_width - 1).max(1) as f32,
+            (height - 1) as f32 / (new_height - 1).max(1) as f32,
+        ];
+        for x in 0..new_width {
+            for y in 0..new_height {
+                let position = [x as f32 * scale[0], y as f32 * scale[1]];
+                let [x0, y0] = [position[0] as u32, position[1] as u32];
+                let [x1, y1] = [(x0 + 1).min(width - 1), (y0 + 1).min(height - 1)];
+                resampled[[x, y]] = bilinear_interpolation(
+                    [self[[x0, y0]], self[[x1, y0]]],
+                    [self[[x0, y1]], self[[x1, y1]]],
+                    [position[0].fract(), position[1].fract()],
+                );
+            }
+        }
+        resampled
+    }
+    //Nearest neighbour downscale until the longest side is at most max_size points
+    pub fn downscale(&self, max_size: u32) -> Self {
+        let [width, height] = self.size();
+        let step = width.max(height).div_ceil(max_size).max(1);
+        let size = [width.div_ceil(step), height.div_ceil(step)];
+        let data = (0..size[0])
+            .flat_map(|x| (0..size[1]).map(move |y| self[[x * step, y * step]]))
+            .collect();
+        Self::from_raw(size, data)
+    }
+    //The compute shaders index the data as x + y * width, while the heightmap is stored column by column
+    pub fn shader_size(&self) -> WorldSize {
+        [self.size[1], self.size[0]]
+    }
     /* pub fn tree_density(&self, point: [u32; 2]) -> f64 {
         self.tree_density[(point[0] as usize, point[1] as usize)]
     } */
@@ -102,36 +155,29 @@ impl Heightmap {
             dy: -(radius as i32),
         }
     }
+    //The heightmap is stored column by column while images are row by row, so the pixels are read by point
     pub fn as_rgba_image(&self, world_settings: &WorldSettings) -> RgbaImage {
         let [width, height] = self.size();
-        RgbaImage::from_raw(
-            width,
-            height,
-            self.data
-                .iter()
-                .map(|&height| {
-                    if (height * WORLD_HEIGHT_SCALE) < world_settings.water_level as f32 {
-                        [0, 124, 155, 255]
-                    } else if (height * WORLD_HEIGHT_SCALE) > SNOW_HEIGHT {
-                        [
-                            (height * 255.0) as u8,
-                            (height * 255.0) as u8,
-                            (height * 255.0) as u8,
-                            255,
-                        ]
-                    } else {
-                        [
-                            (height * 19.0) as u8,
-                            (height * 109.0) as u8,
-                            (height * 21.0) as u8,
-                            255,
-                        ]
-                    }
-                })
-                .flatten()
-                .collect_vec(),
-        )
-        .expect("Failed to convert heightmap to image")
+        RgbaImage::from_fn(width, height, |x, y| {
+            let height = self[[x, y]];
+            if (height * WORLD_HEIGHT_SCALE) < world_settings.water_level as f32 {
+                Rgba([0, 124, 155, 255])
+            } else if (height * WORLD_HEIGHT_SCALE) > SNOW_HEIGHT {
+                Rgba([
+                    (height * 255.0) as u8,
+                    (height * 255.0) as u8,
+                    (height * 255.0) as u8,
+                    255,
+                ])
+            } else {
+                Rgba([
+                    (height * 19.0) as u8,
+                    (height * 109.0) as u8,
+                    (height * 21.0) as u8,
+                    255,
+                ])
+            }
+        })
     }
     pub fn as_dynamic_image(self, world_settings: &WorldSettings) -> DynamicImage {
         DynamicImage::ImageRgba8(self.as_rgba_image(world_settings))
@@ -162,6 +208,15 @@ impl Heightmap {
     }
 }
 
+//For images drawn from the heightmap that have something drawn over them
+pub fn rgba_to_bevy_image(image: RgbaImage) -> Image {
+    Image::from_dynamic(
+        DynamicImage::ImageRgba8(image),
+        false,
+        RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
 #[derive(Debug, Clone)]
 pub struct HeightmapCircle {
     pub center: [i32; 2],
@@ -220,3 +275,66 @@ impl IndexMut<[u32; 2]> for Heightmap {
         &mut self.data[index[0] as usize * self.size[1] as usize + index[1] as usize]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::world_gen::MapSize;
+
+    use super::*;
+
+    #[test]
+    fn resampling_interpolates_between_the_nearest_points() {
+        //Heights that rise along both axes at different rates, which bilinear resampling keeps exactly
+        let data = (0..3)
+            .flat_map(|x| (0..2).map(move |y| 0.1 * x as f32 + 0.3 * y as f32))
+            .collect();
+        let heightmap = Heightmap::from_raw([3, 2], data);
+        let resampled = heightmap.resampled([1, 1]);
+        let last = HEIGHTMAP_CHUNK_SIZE - 1;
+        assert_eq!(resampled.size(), [HEIGHTMAP_CHUNK_SIZE; 2]);
+        for (point, expected) in [
+            ([0, 0], 0.0),
+            ([last, 0], 0.2),
+            ([0, last], 0.3),
+            ([last, last], 0.5),
+            ([last / 2, last], 0.4),
+            ([last / 4, last / 2], 0.2),
+        ] {
+            assert!((resampled[point] - expected).abs() < 1e-6, "{point:?}");
+        }
+
+        //A single raised corner only reaches a quarter of its height in the middle
+        let heightmap = Heightmap::from_raw([2, 2], vec![0.0, 0.0, 0.0, 1.0]);
+        let resampled = heightmap.resampled([1, 1]);
+        assert_eq!(resampled[[last / 2, last / 2]], 0.25);
+        assert_eq!(resampled[[last / 2, last]], 0.5);
+        assert_eq!(resampled[[last, last]], 1.0);
+    }
+
+    #[test]
+    fn images_are_drawn_row_by_row() {
+        //Twice as wide as it is long, with water only in the top left quarter
+        let world_settings = WorldSettings {
+            map_size: MapSize::Custom([2, 1]),
+            ..Default::default()
+        };
+        let mut heightmap = Heightmap::new(world_settings.world_size());
+        let [width, height] = heightmap.size();
+        for x in 0..width {
+            for y in 0..height {
+                heightmap[[x, y]] = if x < width / 2 && y < height / 2 {
+                    0.0
+                } else {
+                    0.25
+                };
+            }
+        }
+        let image = heightmap.as_rgba_image(&world_settings);
+        assert_eq!(image.dimensions(), (width, height));
+        let water = Rgba([0, 124, 155, 255]);
+        assert_eq!(*image.get_pixel(10, 10), water);
+        assert_ne!(*image.get_pixel(width - 10, 10), water);
+        assert_ne!(*image.get_pixel(10, height - 10), water);
+        assert_ne!(*image.get_pixel(width - 10, height - 10), water);
+    }
+}
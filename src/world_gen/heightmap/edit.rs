@@ -0,0 +1,78 @@
+use bevy::prelude::*;
+
+use crate::{world::WorldSize, world_gen::consts::CHUNK_SIZE};
+
+//Sent by anything that changes part of the heightmap, so only the meshes over the changed points are remade
+//Replacing the whole heightmap is still picked up by change detection without an edit
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeightmapEdit {
+    //The corners of the changed rectangle of heightmap points, both inclusive
+    pub min: [u32; 2],
+    pub max: [u32; 2],
+}
+
+impl HeightmapEdit {
+    pub fn new(corner_a: [u32; 2], corner_b: [u32; 2]) -> Self {
+        Self {
+            min: [corner_a[0].min(corner_b[0]), corner_a[1].min(corner_b[1])],
+            max: [corner_a[0].max(corner_b[0]), corner_a[1].max(corner_b[1])],
+        }
+    }
+    //The smallest edit covering every point, None if there are none
+    pub fn from_points(points: impl IntoIterator<Item = [u32; 2]>) -> Option<Self> {
+        points
+            .into_iter()
+            .map(|point| Self::new(point, point))
+            .reduce(Self::union)
+    }
+    pub fn union(self, other: Self) -> Self {
+        Self::new(
+            [self.min[0].min(other.min[0]), self.min[1].min(other.min[1])],
+            [self.max[0].max(other.max[0]), self.max[1].max(other.max[1])],
+        )
+    }
+    //Every changed point that is on the heightmap
+    pub fn points(&self, heightmap_size: WorldSize) -> impl Iterator<Item = [u32; 2]> {
+        let min = self.min;
+        let max = [
+            self.max[0].min(heightmap_size[0].saturating_sub(1)),
+            self.max[1].min(heightmap_size[1].saturating_sub(1)),
+        ];
+        (min[0]..=max[0]).flat_map(move |x| (min[1]..=max[1]).map(move |y| [x, y]))
+    }
+    //Every chunk with a vertex the edit could have changed
+    //Vertex normals and terrain types come from the neighbouring points too, so the rectangle is grown by a point,
+    //and neighbouring chunks share the points on their border, so an edit on the border remakes both
+    pub fn chunks(&self, world_size: WorldSize) -> Vec<[u32; 2]> {
+        let [first_x, first_y] = self.min.map(|min| min.saturating_sub(2) / CHUNK_SIZE);
+        let [last_x, last_y] = self.max.map(|max| (max + 1) / CHUNK_SIZE);
+        let last_x = last_x.min(world_size[0].saturating_sub(1));
+        let last_y = last_y.min(world_size[1].saturating_sub(1));
+        (first_x..=last_x)
+            .flat_map(|x| (first_y..=last_y).map(move |y| [x, y]))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_remake_every_chunk_sharing_their_points() {
+        let inside = HeightmapEdit::new([10, 20], [5, 30]);
+        assert_eq!(inside.min, [5, 20]);
+        assert_eq!(inside.chunks([4, 4]), vec![[0, 0]]);
+        //On the border between the first two chunks
+        let border = HeightmapEdit::new([CHUNK_SIZE, 5], [CHUNK_SIZE, 5]);
+        assert_eq!(border.chunks([4, 4]), vec![[0, 0], [1, 0]]);
+        //Next to the border, the normal of the border point changes
+        let next_to_border = HeightmapEdit::new([CHUNK_SIZE + 1, 5], [CHUNK_SIZE + 1, 5]);
+        assert_eq!(next_to_border.chunks([4, 4]), vec![[0, 0], [1, 0]]);
+        let far_from_border = HeightmapEdit::new([CHUNK_SIZE + 2, 5], [CHUNK_SIZE + 2, 5]);
+        assert_eq!(far_from_border.chunks([4, 4]), vec![[1, 0]]);
+        //Off the far edge of the map
+        let off_map = HeightmapEdit::new([CHUNK_SIZE * 4, 0], [CHUNK_SIZE * 5, 0]);
+        assert_eq!(off_map.chunks([4, 4]), vec![[3, 0]]);
+    }
+}
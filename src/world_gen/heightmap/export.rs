@@ -0,0 +1,227 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use image::{ImageBuffer, Luma};
+
+use crate::world_gen::{mesh_gen::create_world_mesh_vecs, WorldSettings};
+
+use super::Heightmap;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    Png16,
+    //Little endian f32s row by row, the same layout the raw import reads
+    RawF32,
+    Obj,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] =
+        [ExportFormat::Png16, ExportFormat::RawF32, ExportFormat::Obj];
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportFormat::Png16 => "16-bit PNG",
+            ExportFormat::RawF32 => "Raw f32",
+            ExportFormat::Obj => "OBJ mesh",
+        }
+    }
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Png16 => "png",
+            ExportFormat::RawF32 => "r32",
+            ExportFormat::Obj => "obj",
+        }
+    }
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.extension() == extension.to_lowercase())
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    Image(image::ImageError),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Io(error) => write!(f, "Couldn't write the export: {error}"),
+            ExportError::Image(error) => write!(f, "Couldn't encode the heightmap: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<io::Error> for ExportError {
+    fn from(error: io::Error) -> Self {
+        ExportError::Io(error)
+    }
+}
+
+impl From<image::ImageError> for ExportError {
+    fn from(error: image::ImageError) -> Self {
+        ExportError::Image(error)
+    }
+}
+
+pub fn export_heightmap(
+    heightmap: &Heightmap,
+    world_settings: &WorldSettings,
+    path: &Path,
+    format: ExportFormat,
+) -> Result<(), ExportError> {
+    match format {
+        ExportFormat::Png16 => export_png(heightmap, path),
+        ExportFormat::RawF32 => export_raw(heightmap, path),
+        ExportFormat::Obj => export_obj(heightmap, world_settings, path),
+    }
+}
+
+fn export_png(heightmap: &Heightmap, path: &Path) -> Result<(), ExportError> {
+    let [width, height] = heightmap.size();
+    let image: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::from_fn(width, height, |x, y| {
+        Luma([(heightmap[[x, y]].clamp(0.0, 1.0) * f32::from(u16::MAX)).round() as u16])
+    });
+    image.save_with_format(path, image::ImageFormat::Png)?;
+    Ok(())
+}
+
+fn export_raw(heightmap: &Heightmap, path: &Path) -> Result<(), ExportError> {
+    let [width, height] = heightmap.size();
+    let mut bytes = Vec::with_capacity((width * height) as usize * 4);
+    for y in 0..height {
+        for x in 0..width {
+            bytes.extend(heightmap[[x, y]].to_le_bytes());
+        }
+    }
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+//Every heightmap point is a vertex, so big maps make big files
+fn export_obj(
+    heightmap: &Heightmap,
+    world_settings: &WorldSettings,
+    path: &Path,
+) -> Result<(), ExportError> {
+    let (vertices, _, indices, normals) = create_world_mesh_vecs(heightmap, world_settings, 1);
+    let mut file = BufWriter::new(File::create(path)?);
+    writeln!(file, "# Seed {}", world_settings.seed())?;
+    for [x, y, z] in vertices {
+        writeln!(file, "v {x} {y} {z}")?;
+    }
+    for [x, y, z] in normals {
+        writeln!(file, "vn {x} {y} {z}")?;
+    }
+    //Obj indices start at 1, and each vertex has its own normal
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0] + 1, triangle[1] + 1, triangle[2] + 1];
+        writeln!(file, "f {a}//{a} {b}//{b} {c}//{c}")?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::world_gen::{heightmap::import::import_heightmap, MapSize};
+
+    use super::*;
+
+    //Twice as wide as it is long, with heights that change differently along each axis so a transpose shows up
+    fn test_world() -> (Heightmap, WorldSettings) {
+        let world_settings = WorldSettings {
+            map_size: MapSize::Custom([2, 1]),
+            ..Default::default()
+        };
+        let mut heightmap = Heightmap::new(world_settings.world_size());
+        let [width, height] = heightmap.size();
+        for x in 0..width {
+            for y in 0..height {
+                heightmap[[x, y]] =
+                    0.75 * x as f32 / width as f32 + 0.25 * y as f32 / height as f32;
+            }
+        }
+        (heightmap, world_settings)
+    }
+
+    fn temp_path(name: &str, format: ExportFormat) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "{name}-{}.{}",
+            std::process::id(),
+            format.extension()
+        ))
+    }
+
+    #[test]
+    fn png_exports_import_back() {
+        let (heightmap, world_settings) = test_world();
+        let path = temp_path("export-png", ExportFormat::Png16);
+        export_heightmap(&heightmap, &world_settings, &path, ExportFormat::Png16).unwrap();
+        let imported = import_heightmap(&path);
+        fs::remove_file(&path).unwrap();
+        let imported = imported.unwrap().heightmap;
+        assert_eq!(imported.size(), heightmap.size());
+        for (imported, height) in imported.data.iter().zip(&heightmap.data) {
+            assert!((imported - height).abs() <= 1.0 / f32::from(u16::MAX));
+        }
+    }
+
+    #[test]
+    fn raw_exports_import_back() {
+        //Raw files have no header, so the layout of a map that isn't square is checked by hand
+        let (heightmap, world_settings) = test_world();
+        let [width, height] = heightmap.size();
+        let path = temp_path("export-raw", ExportFormat::RawF32);
+        export_heightmap(&heightmap, &world_settings, &path, ExportFormat::RawF32).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(bytes.len(), (width * height) as usize * 4);
+        for (index, raw_height) in bytes.chunks_exact(4).enumerate() {
+            let [x, y] = [index as u32 % width, index as u32 / width];
+            assert_eq!(
+                f32::from_le_bytes(raw_height.try_into().unwrap()),
+                heightmap[[x, y]]
+            );
+        }
+
+        //The importer only takes square raw files
+        let data = heightmap.data[..(height * height) as usize].to_vec();
+        let square = Heightmap::from_raw([height, height], data);
+        let path = temp_path("export-raw-square", ExportFormat::RawF32);
+        export_heightmap(&square, &world_settings, &path, ExportFormat::RawF32).unwrap();
+        let imported = import_heightmap(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(imported.unwrap().heightmap.data, square.data);
+    }
+
+    #[test]
+    fn obj_exports_have_every_vertex_and_face() {
+        let (heightmap, world_settings) = test_world();
+        let path = temp_path("export-obj", ExportFormat::Obj);
+        export_heightmap(&heightmap, &world_settings, &path, ExportFormat::Obj).unwrap();
+        let obj = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let (vertices, _, indices, normals) =
+            create_world_mesh_vecs(&heightmap, &world_settings, 1);
+        let [width, height] = world_settings.tile_world_size();
+        assert_eq!(vertices.len(), ((width + 1) * (height + 1)) as usize);
+        let count = |prefix: &str| obj.lines().filter(|line| line.starts_with(prefix)).count();
+        assert_eq!(count("v "), vertices.len());
+        assert_eq!(count("vn "), normals.len());
+        assert_eq!(count("f "), indices.len() / 3);
+        assert_eq!(indices.len() / 3, (width * height * 2) as usize);
+    }
+}
@@ -0,0 +1,256 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use bevy::{
+    prelude::*,
+    tasks::{block_on, AsyncComputeTaskPool, Task},
+};
+use image::RgbaImage;
+use ordered_float::OrderedFloat;
+use strum::IntoEnumIterator;
+
+use crate::{
+    utils::{
+        direction::CardinalDirection,
+        math::{AsI32, AsU32},
+    },
+    world::{terraforming::Terraforming, WorldSize},
+    world_gen::pipeline::WorldGenPipeline,
+};
+
+use super::Heightmap;
+
+//Added to every filled point, so flat filled depressions still drain towards their outlet
+const FILL_EPSILON: f32 = 1e-6;
+//Points with less of the map draining through them than this aren't drawn in the overlay
+const OVERLAY_THRESHOLD: f32 = 0.3;
+const OVERLAY_COLOUR: [f32; 3] = [40.0, 90.0, 255.0];
+
+//D8 flow directions and flow accumulation, stored in the same order as the heightmap
+#[derive(Resource, Clone, Debug)]
+pub struct Hydrology {
+    size: WorldSize,
+    //The heightmap with every depression filled up to where it overflows
+    filled: Vec<f32>,
+    //Where the water from each point goes, None where it flows off the map
+    directions: Vec<Option<CardinalDirection>>,
+    //How many points drain through each point, including the point itself
+    accumulation: Vec<u32>,
+}
+
+impl Hydrology {
+    pub fn new(heightmap: &Heightmap) -> Self {
+        let size = heightmap.size();
+        let length = heightmap.data.len();
+        let mut hydrology = Self {
+            size,
+            filled: heightmap.data.clone(),
+            directions: vec![None; length],
+            accumulation: vec![1; length],
+        };
+        hydrology.fill_depressions();
+        for index in 0..length {
+            hydrology.directions[index] = hydrology.steepest_descent(hydrology.point(index));
+        }
+        //Water only flows downhill on the filled heightmap, so going from the top down visits every point before the points it drains into
+        let mut order = (0..length).collect::<Vec<_>>();
+        order.sort_unstable_by(|a, b| hydrology.filled[*b].total_cmp(&hydrology.filled[*a]));
+        for index in order {
+            if let Some(downstream) = hydrology.downstream(hydrology.point(index)) {
+                let downstream = hydrology.index(downstream);
+                hydrology.accumulation[downstream] += hydrology.accumulation[index];
+            }
+        }
+        hydrology
+    }
+    pub fn size(&self) -> WorldSize {
+        self.size
+    }
+    pub fn flow_direction(&self, point: [u32; 2]) -> Option<CardinalDirection> {
+        self.directions[self.index(point)]
+    }
+    //The next point the water flows to
+    pub fn downstream(&self, point: [u32; 2]) -> Option<[u32; 2]> {
+        self.neighbour(point, self.flow_direction(point)?)
+    }
+    pub fn accumulation(&self, point: [u32; 2]) -> u32 {
+        self.accumulation[self.index(point)]
+    }
+    //How high water would pool here, the same as the heightmap outside of depressions
+    pub fn filled_height(&self, point: [u32; 2]) -> f32 {
+        self.filled[self.index(point)]
+    }
+    //Every point that drains through the outlet, including the outlet
+    pub fn watershed(&self, outlet: [u32; 2]) -> Vec<[u32; 2]> {
+        let mut watershed = vec![outlet];
+        let mut next = 0;
+        while let Some(&point) = watershed.get(next) {
+            next += 1;
+            for direction in CardinalDirection::iter() {
+                if let Some(neighbour) = self.neighbour(point, direction) {
+                    if self.downstream(neighbour) == Some(point) {
+                        watershed.push(neighbour);
+                    }
+                }
+            }
+        }
+        watershed
+    }
+    //Tints the points that a lot of water drains through, the image has to come from the same heightmap
+    pub fn draw_overlay(&self, image: &mut RgbaImage) {
+        let max = self.accumulation.iter().max().copied().unwrap_or(1).max(2);
+        let max = (max as f32).ln();
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let strength = (self.accumulation([x, y]) as f32).ln() / max;
+            if strength < OVERLAY_THRESHOLD {
+                continue;
+            }
+            for (channel, colour) in pixel.0.iter_mut().zip(OVERLAY_COLOUR) {
+                *channel = (f32::from(*channel) * (1.0 - strength) + colour * strength) as u8;
+            }
+        }
+    }
+    fn index(&self, point: [u32; 2]) -> usize {
+        point[0] as usize * self.size[1] as usize + point[1] as usize
+    }
+    fn point(&self, index: usize) -> [u32; 2] {
+        [
+            (index / self.size[1] as usize) as u32,
+            (index % self.size[1] as usize) as u32,
+        ]
+    }
+    fn neighbour(&self, point: [u32; 2], direction: CardinalDirection) -> Option<[u32; 2]> {
+        let neighbour = point.as_i32() + direction;
+        (neighbour[0] >= 0
+            && neighbour[1] >= 0
+            && neighbour[0] < self.size[0] as i32
+            && neighbour[1] < self.size[1] as i32)
+            .then(|| neighbour.as_u32())
+    }
+    fn on_edge(&self, [x, y]: [u32; 2]) -> bool {
+        x == 0 || y == 0 || x + 1 == self.size[0] || y + 1 == self.size[1]
+    }
+    //Priority flood from the edges of the map, raising every point that can't drain to the level it overflows at
+    fn fill_depressions(&mut self) {
+        let mut done = vec![false; self.filled.len()];
+        let mut queue = BinaryHeap::new();
+        for index in 0..self.filled.len() {
+            if self.on_edge(self.point(index)) {
+                done[index] = true;
+                queue.push(Reverse((OrderedFloat(self.filled[index]), index)));
+            }
+        }
+        while let Some(Reverse((OrderedFloat(height), index))) = queue.pop() {
+            let point = self.point(index);
+            for direction in CardinalDirection::iter() {
+                let Some(neighbour) = self.neighbour(point, direction) else {
+                    continue;
+                };
+                let neighbour = self.index(neighbour);
+                if done[neighbour] {
+                    continue;
+                }
+                done[neighbour] = true;
+                self.filled[neighbour] = self.filled[neighbour].max(height + FILL_EPSILON);
+                queue.push(Reverse((OrderedFloat(self.filled[neighbour]), neighbour)));
+            }
+        }
+    }
+    //Diagonal neighbours are further away, so their drop is scaled down
+    fn steepest_descent(&self, point: [u32; 2]) -> Option<CardinalDirection> {
+        if self.on_edge(point) {
+            return None;
+        }
+        let height = self.filled_height(point);
+        CardinalDirection::iter()
+            .filter_map(|direction| {
+                let neighbour = self.neighbour(point, direction)?;
+                let distance = if neighbour[0] != point[0] && neighbour[1] != point[1] {
+                    std::f32::consts::SQRT_2
+                } else {
+                    1.0
+                };
+                let slope = (height - self.filled_height(neighbour)) / distance;
+                (slope > 0.0).then_some((direction, slope))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(direction, _)| direction)
+    }
+}
+
+//Recomputed in the background whenever the heightmap changes, but not while it is still being generated
+//or while a terraforming stroke is being painted, so it runs once the heightmap settles
+pub fn update_hydrology(
+    mut commands: Commands,
+    heightmap: Res<Heightmap>,
+    pipeline: Option<Res<WorldGenPipeline>>,
+    terraforming: Option<Res<Terraforming>>,
+    mut task: Local<Option<Task<Hydrology>>>,
+    mut outdated: Local<bool>,
+) {
+    if heightmap.is_changed() {
+        *outdated = true;
+    }
+    if task.as_ref().is_some_and(Task::is_finished) {
+        commands.insert_resource(block_on(task.take().unwrap()));
+    }
+    let generating = pipeline.is_some_and(|pipeline| pipeline.is_generating());
+    let painting = terraforming.is_some_and(|terraforming| terraforming.is_painting());
+    //Dropping a task that has already started doesn't stop it, so changes made while one is running
+    //wait for it to finish instead of piling more tasks up
+    if *outdated && !generating && !painting && task.is_none() {
+        *outdated = false;
+        let heightmap = heightmap.clone();
+        *task = Some(AsyncComputeTaskPool::get().spawn(async move { Hydrology::new(&heightmap) }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Slopes down towards x = 0
+    fn slope(size: WorldSize) -> Heightmap {
+        let data = (0..size[0])
+            .flat_map(|x| (0..size[1]).map(move |_| x as f32 / size[0] as f32))
+            .collect();
+        Heightmap::from_raw(size, data)
+    }
+
+    #[test]
+    fn water_flows_down_the_slope() {
+        let hydrology = Hydrology::new(&slope([8, 5]));
+        assert_eq!(
+            hydrology.flow_direction([4, 2]),
+            Some(CardinalDirection::West)
+        );
+        assert_eq!(hydrology.flow_direction([0, 2]), None);
+        //The top of the slope is on the edge too, so it flows off the map instead
+        assert_eq!(hydrology.accumulation([0, 2]), 7);
+        assert_eq!(hydrology.watershed([0, 2]).len(), 7);
+    }
+
+    #[test]
+    fn depressions_are_filled_and_drain() {
+        let mut heightmap = Heightmap::from_raw([5, 5], vec![1.0; 25]);
+        heightmap[[2, 2]] = 0.0;
+        let hydrology = Hydrology::new(&heightmap);
+        assert!(hydrology.filled_height([2, 2]) > 1.0);
+        assert!(hydrology.downstream([2, 2]).is_some());
+        assert_eq!(hydrology.accumulation([2, 2]), 1);
+    }
+
+    #[test]
+    fn overlay_tints_the_pixel_over_each_point() {
+        let hydrology = Hydrology::new(&slope([8, 5]));
+        let mut image = RgbaImage::new(8, 5);
+        hydrology.draw_overlay(&mut image);
+        let max = (*hydrology.accumulation.iter().max().unwrap() as f32).ln();
+        let mut tinted = 0;
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let strength = (hydrology.accumulation([x, y]) as f32).ln() / max;
+            assert_eq!(pixel.0 != [0; 4], strength >= OVERLAY_THRESHOLD, "{x}, {y}");
+            tinted += usize::from(pixel.0 != [0; 4]);
+        }
+        assert!(tinted > 0);
+    }
+}
@@ -0,0 +1,186 @@
+use std::{fmt, fs, io, path::Path};
+
+use bevy::prelude::*;
+
+use super::Heightmap;
+
+//File extensions that can be imported, raw files are square and little endian
+pub const IMPORT_EXTENSIONS: [&str; 6] = ["png", "tif", "tiff", "r16", "r32", "raw"];
+
+//A heightmap from an external tool, kept at its own resolution and stretched over whatever map size is picked
+#[derive(Resource, Clone)]
+pub struct ImportedHeightmap {
+    pub name: String,
+    pub heightmap: Heightmap,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(io::Error),
+    Image(image::ImageError),
+    UnsupportedFormat(String),
+    InvalidRaw(String),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Io(error) => write!(f, "Couldn't read the heightmap: {error}"),
+            ImportError::Image(error) => write!(f, "Couldn't decode the heightmap: {error}"),
+            ImportError::UnsupportedFormat(extension) => {
+                write!(f, "Can't import .{extension} files")
+            }
+            ImportError::InvalidRaw(reason) => write!(f, "Invalid raw heightmap: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<io::Error> for ImportError {
+    fn from(error: io::Error) -> Self {
+        ImportError::Io(error)
+    }
+}
+
+impl From<image::ImageError> for ImportError {
+    fn from(error: image::ImageError) -> Self {
+        ImportError::Image(error)
+    }
+}
+
+pub fn import_heightmap(path: &Path) -> Result<ImportedHeightmap, ImportError> {
+    let extension = path
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    let heightmap = match extension.as_str() {
+        "png" | "tif" | "tiff" => from_image(path)?,
+        "r16" | "raw" => from_raw(&fs::read(path)?, 2)?,
+        "r32" => from_raw(&fs::read(path)?, 4)?,
+        _ => return Err(ImportError::UnsupportedFormat(extension)),
+    };
+    Ok(ImportedHeightmap {
+        name: path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+        heightmap,
+    })
+}
+
+//8 bit images are widened to 16 bits, colour images are converted to grayscale
+fn from_image(path: &Path) -> Result<Heightmap, ImportError> {
+    let image = image::open(path)?.into_luma16();
+    let (width, height) = image.dimensions();
+    let mut heightmap = Heightmap::from_raw([width, height], vec![0.0; (width * height) as usize]);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        heightmap[[x, y]] = f32::from(pixel.0[0]) / f32::from(u16::MAX);
+    }
+    Ok(heightmap)
+}
+
+//Raw files have no header, so they have to be square
+fn from_raw(bytes: &[u8], bytes_per_height: usize) -> Result<Heightmap, ImportError> {
+    if bytes.len() % bytes_per_height != 0 {
+        return Err(ImportError::InvalidRaw(format!(
+            "{} bytes isn't a whole number of {}-bit heights",
+            bytes.len(),
+            bytes_per_height * 8
+        )));
+    }
+    let heights = bytes.len() / bytes_per_height;
+    let side = (heights as f64).sqrt() as u32;
+    if (side * side) as usize != heights || side == 0 {
+        return Err(ImportError::InvalidRaw(format!(
+            "{heights} heights don't make a square heightmap"
+        )));
+    }
+
+    let mut heightmap = Heightmap::from_raw([side, side], vec![0.0; heights]);
+    for (index, height) in bytes.chunks_exact(bytes_per_height).enumerate() {
+        let height = match bytes_per_height {
+            2 => f32::from(u16::from_le_bytes(height.try_into().unwrap())) / f32::from(u16::MAX),
+            _ => f32::from_le_bytes(height.try_into().unwrap()).clamp(0.0, 1.0),
+        };
+        //Raw files are stored row by row
+        let [x, y] = [index as u32 % side, index as u32 / side];
+        heightmap[[x, y]] = height;
+    }
+    Ok(heightmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, ImageBuffer, Luma};
+
+    use super::*;
+
+    //Saves the image somewhere temporary and imports it back
+    fn import_image(name: &str, image: DynamicImage) -> Heightmap {
+        let path = std::env::temp_dir().join(format!("{name}-{}.png", std::process::id()));
+        image.save(&path).unwrap();
+        let imported = import_heightmap(&path);
+        fs::remove_file(&path).unwrap();
+        imported.unwrap().heightmap
+    }
+
+    #[test]
+    fn png_pixels_become_heights() {
+        let image = ImageBuffer::from_fn(3, 2, |x, y| Luma([(x * 100 + y * 50) as u8]));
+        let heightmap = import_image("import-8-bit", DynamicImage::ImageLuma8(image));
+        assert_eq!(heightmap.size(), [3, 2]);
+        for (x, y) in [(0, 0), (2, 0), (1, 1), (2, 1)] {
+            let expected = (x * 100 + y * 50) as f32 / f32::from(u8::MAX);
+            assert!((heightmap[[x, y]] - expected).abs() < 1e-6);
+        }
+
+        let image = ImageBuffer::from_fn(3, 2, |x, y| Luma([(x * 20000 + y * 1000) as u16]));
+        let heightmap = import_image("import-16-bit", DynamicImage::ImageLuma16(image));
+        assert_eq!(heightmap.size(), [3, 2]);
+        for (x, y) in [(0, 0), (2, 0), (1, 1), (2, 1)] {
+            let expected = (x * 20000 + y * 1000) as f32 / f32::from(u16::MAX);
+            assert_eq!(heightmap[[x, y]], expected);
+        }
+    }
+
+    #[test]
+    fn raw_files_are_read_row_by_row() {
+        let heights = [0.0, 0.25, 0.5, 1.0];
+        let r16: Vec<u8> = heights
+            .iter()
+            .flat_map(|height| ((height * f32::from(u16::MAX)) as u16).to_le_bytes())
+            .collect();
+        let r32: Vec<u8> = heights
+            .iter()
+            .flat_map(|height| height.to_le_bytes())
+            .collect();
+        for heightmap in [from_raw(&r16, 2).unwrap(), from_raw(&r32, 4).unwrap()] {
+            assert_eq!(heightmap.size(), [2, 2]);
+            assert_eq!(heightmap[[0, 0]], 0.0);
+            assert_eq!(heightmap[[1, 1]], 1.0);
+            //The second height is further along the first row, not down the first column
+            assert!((heightmap[[1, 0]] - 0.25).abs() < 1e-4);
+            assert!((heightmap[[0, 1]] - 0.5).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn raw_files_that_arent_square_are_rejected() {
+        assert!(matches!(
+            from_raw(&[0; 6 * 2], 2),
+            Err(ImportError::InvalidRaw(_))
+        ));
+        assert!(matches!(
+            from_raw(&[0; 3 * 4], 4),
+            Err(ImportError::InvalidRaw(_))
+        ));
+        assert!(matches!(
+            from_raw(&[0; 5], 2),
+            Err(ImportError::InvalidRaw(_))
+        ));
+        assert!(matches!(from_raw(&[], 2), Err(ImportError::InvalidRaw(_))));
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use bevy::{
     prelude::*,
     reflect::List,
@@ -7,26 +9,31 @@ use bevy::{
 
 use itertools::Itertools;
 use rand::{prelude::Rng, rngs::StdRng, SeedableRng};
-use smooth_bevy_cameras::{controllers::orbit::OrbitCameraController, LookTransform};
 use strum::IntoEnumIterator;
 
 use crate::{
-    assets::{get_terrain_texture_uv, TerrainTextureAtlas, TerrainType},
+    assets::{terrain_weights, TerrainTextureAtlas, TerrainType},
     utils::math::unnormalized_normal_array,
-    world::WorldEntity,
+    world::{WorldEntity, WorldSize},
     world_gen::{
-        consts::{CHUNK_SIZE, CHUNK_WORLD_SIZE, LOD_LEVELS, TILE_WORLD_SIZE},
-        heightmap::Heightmap,
+        consts::{CHUNK_SIZE, LOD_LEVELS},
+        heightmap::{edit::HeightmapEdit, Heightmap},
     },
     GameState,
 };
 
+pub mod chunk_cache;
+pub mod culling;
+
 #[derive(Component)]
 pub struct WorldMesh;
 #[derive(Component)]
 pub struct TreeMesh;
 #[derive(Component)]
 pub struct WaterMesh;
+//The walls around the edge of the map
+#[derive(Component)]
+pub struct EdgeMesh;
 #[derive(Component)]
 pub struct LODLevel(pub u32);
 #[derive(Component)]
@@ -37,287 +44,403 @@ use super::{
     WorldSettings,
 };
 
-pub fn level_of_detail(
-    mut meshes: Query<(&LODLevel, &ChunkPosition, &mut Visibility)>,
-    cameras: Query<(&OrbitCameraController, &mut LookTransform, &Transform)>,
-) {
-    let (_, transform, _) = cameras.iter().find(|c| c.0.enabled).expect("No camera");
-    for (lod, chunk_position, mut visibility) in meshes.iter_mut() {
-        //Convert chunk position to world position
-        let chunk_position = [
-            (chunk_position.0[0] as f32 * CHUNK_SIZE as f32) + CHUNK_SIZE as f32 / 2.0,
-            (chunk_position.0[1] as f32 * CHUNK_SIZE as f32) + CHUNK_SIZE as f32 / 2.0,
-        ];
-        let camera_position = transform.eye.xz();
-        let target_position = transform.target.xz();
-        /* let camera_position = Vec2::new(
-            (CHUNK_WORLD_SIZE[0] * CHUNK_SIZE) as f32 * 0.5,
-            (CHUNK_WORLD_SIZE[1] * CHUNK_SIZE) as f32 * 0.5,
-        ); */
-        let camera_distance = ((camera_position
-            .distance(Vec2::new(chunk_position[0], chunk_position[1]))
-            / CHUNK_SIZE as f32)
-            .round() as u32)
-            .clamp(1, LOD_LEVELS);
-        let target_distance = ((target_position
-            .distance(Vec2::new(chunk_position[0], chunk_position[1]))
-            / CHUNK_SIZE as f32)
-            .round() as u32)
-            .clamp(1, LOD_LEVELS);
-        let distance = camera_distance.min(target_distance);
-        //Show the correct LOD mesh based on distance
-        if lod.0 != distance {
-            *visibility = Visibility::Hidden;
-        } else {
-            *visibility = Visibility::Visible;
-        }
-    }
-}
+//How far the skirts reach below the lowest point a neighbouring chunk's border could be at
+const SKIRT_DEPTH: f32 = TILE_SIZE;
 
 #[derive(Resource, Default, Copy, Clone, Debug, Eq, PartialEq)]
 pub struct ExtractedGameState(pub GameState);
 
+//Edits only remake the edge walls of the chunks under them, any other heightmap change remakes them all
 pub fn generate_world_mesh(
     mut commands: Commands,
-    world_mesh_query: Query<Entity, With<WorldMesh>>,
+    edge_mesh_query: Query<(Entity, &ChunkPosition), With<EdgeMesh>>,
     heightmap: Res<Heightmap>,
     world_settings: Res<WorldSettings>,
+    mut heightmap_edits: EventReader<HeightmapEdit>,
     water_mesh: Query<Entity, With<WaterMesh>>,
     mut mesh_assets: ResMut<Assets<Mesh>>,
     mut material_assets: ResMut<Assets<StandardMaterial>>,
     terrain_texture_atlas: Res<TerrainTextureAtlas>,
 ) {
-    if world_mesh_query.is_empty() || heightmap.is_changed() {
-        //Generate Water Mesh
-        if water_mesh.is_empty() {
-            commands
-                .spawn(PbrBundle {
-                    mesh: mesh_assets.add(
-                        Plane3d::default()
-                            .mesh()
-                            .size(TILE_WORLD_SIZE[0] as f32, TILE_WORLD_SIZE[1] as f32),
-                    ),
-                    material: material_assets.add(Color::BLUE),
-                    transform: Transform::from_translation(Vec3::new(
-                        (TILE_WORLD_SIZE[0] as f32 / 2.0) - TILE_SIZE / 2.,
-                        world_settings.water_level as f32,
-                        (TILE_WORLD_SIZE[1] as f32 / 2.0) - TILE_SIZE / 2.,
-                    )),
-                    ..default()
-                })
-                .insert(WaterMesh);
-        }
-
-        let start_time = std::time::Instant::now();
+    let world_size = world_settings.world_size();
+    let edited = heightmap_edits
+        .read()
+        .flat_map(|edit| edit.chunks(world_size))
+        .collect::<HashSet<_>>();
+    let chunks = if edge_mesh_query.is_empty() || (heightmap.is_changed() && edited.is_empty()) {
+        (0..world_size[0])
+            .flat_map(|x| (0..world_size[1]).map(move |y| [x, y]))
+            .filter(|&chunk| on_world_edge(chunk, world_size))
+            .collect_vec()
+    } else {
+        edited
+            .into_iter()
+            .filter(|&chunk| on_world_edge(chunk, world_size))
+            .collect_vec()
+    };
+    if chunks.is_empty() {
+        return;
+    }
+    let tile_world_size = world_settings.tile_world_size();
+    //Generate Water Mesh
+    if water_mesh.is_empty() {
+        commands
+            .spawn(PbrBundle {
+                mesh: mesh_assets.add(
+                    Plane3d::default()
+                        .mesh()
+                        .size(tile_world_size[0] as f32, tile_world_size[1] as f32),
+                ),
+                material: material_assets.add(Color::BLUE),
+                transform: Transform::from_translation(Vec3::new(
+                    (tile_world_size[0] as f32 / 2.0) - TILE_SIZE / 2.,
+                    world_settings.water_level as f32,
+                    (tile_world_size[1] as f32 / 2.0) - TILE_SIZE / 2.,
+                )),
+                ..default()
+            })
+            .insert(WaterMesh);
+    }
 
-        let random_number_generator = StdRng::seed_from_u64(world_settings.seed() as u64);
+    let start_time = std::time::Instant::now();
 
-        //Despawn old meshes
-        for entity in world_mesh_query.iter() {
+    //Despawn old meshes, the chunk meshes are remade on demand by the chunk cache
+    for (entity, chunk_position) in edge_mesh_query.iter() {
+        if chunks.contains(&chunk_position.0) {
             commands.entity(entity).despawn();
         }
-        //Generate chunk meshes
-        let thread_pool = ComputeTaskPool::get();
-        let heightmap_ref = &heightmap;
-        for lod_count in 0..=LOD_LEVELS as usize {
-            let lod = if lod_count == 0 { 1 } else { lod_count * 2 };
-            let results = thread_pool.scope(|s| {
-                for chunk_y in 0..CHUNK_WORLD_SIZE[1] {
-                    for chunk_x in 0..CHUNK_WORLD_SIZE[0] {
-                        let mut rng = random_number_generator.clone();
-                        s.spawn(async move {
-                            let mut grid_mesh = Mesh::new(
-                                PrimitiveTopology::TriangleList,
-                                RenderAssetUsages::RENDER_WORLD,
-                            );
-                            let mut vertices = Vec::new();
-                            let mut uvs = Vec::new();
-                            let mut indices = Vec::new();
-                            let mut normals = Vec::new();
-                            let mut indices_count = 0;
-
-                            for y in 0..CHUNK_SIZE {
-                                for x in 0..CHUNK_SIZE {
-                                    /* let decreased_lod = lod.saturating_sub(1).max(1);
-                                    //Decrease the LOD level by 1 for the edges for seamless LOD transitions
-                                    let current_lod = if (y == 0
-                                        || y == CHUNK_SIZE - 1
-                                        || x == 0
-                                        || x == CHUNK_SIZE - 1)
-                                        && ((y as usize) % (decreased_lod) == 0
-                                            && (x as usize) % (decreased_lod) == 0)
-                                    {
-                                        decreased_lod
-                                    } else  */
-                                    if (y as usize) % (lod) == 0 && (x as usize) % (lod) == 0 {
-                                        /* continue; */
-                                        lod
-                                    } else {
-                                        continue;
-                                    };
-                                    let (new_vertices, uv, index, normal) = create_terrain_mesh(
-                                        [(chunk_x * CHUNK_SIZE) + x, (chunk_y * CHUNK_SIZE) + y],
-                                        heightmap_ref,
-                                        &mut rng,
-                                        indices_count,
-                                        lod as u32,
-                                    );
-                                    indices_count += new_vertices.len() as u32;
-                                    vertices.extend(new_vertices);
-                                    uvs.extend(uv);
-                                    indices.extend(index);
-                                    normals.extend(normal);
-                                }
-                            }
-
-                            grid_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-                            grid_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-                            grid_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
-
-                            grid_mesh.insert_indices(Indices::U32(indices));
-
-                            (grid_mesh, [chunk_x, chunk_y])
-                        });
-                    }
-                }
+    }
+    let thread_pool = ComputeTaskPool::get();
+    let heightmap_ref = &heightmap;
+    //Generate Edge meshes
+    let results = thread_pool.scope(|s| {
+        for &chunk_position in &chunks {
+            s.spawn(async move {
+                (
+                    chunk_position,
+                    create_chunk_edge_mesh(chunk_position, heightmap_ref, world_size),
+                )
             });
-            for (mesh, position) in results {
-                let mesh = mesh_assets.add(mesh);
-
-                let material = terrain_texture_atlas.handle.clone();
-
-                commands
-                    .spawn(PbrBundle {
-                        mesh,
-                        material,
-                        ..Default::default()
-                    })
-                    .insert(WorldMesh)
-                    .insert(WorldEntity)
-                    .insert(LODLevel(lod_count as u32))
-                    .insert(ChunkPosition(position));
-            }
         }
-        //Generate Edge meshes
-        let results = thread_pool.scope(|s| {
-            for chunk_y in 0..CHUNK_WORLD_SIZE[1] {
-                for chunk_x in 0..CHUNK_WORLD_SIZE[0] {
-                    let mut x_offset = 0;
-                    let mut y_offset = 0;
-                    let x_direction = match chunk_x {
-                        0 => Some(FaceDirection::East),
-                        x if x == CHUNK_WORLD_SIZE[0] - 1 => {
-                            x_offset = CHUNK_SIZE - 1;
-                            Some(FaceDirection::West)
-                        }
-                        _ => None,
-                    };
-                    let y_direction = match chunk_y {
-                        0 => Some(FaceDirection::South),
-                        y if y == CHUNK_WORLD_SIZE[1] - 1 => {
-                            y_offset = CHUNK_SIZE - 1;
-                            Some(FaceDirection::North)
-                        }
-                        _ => None,
-                    };
-                    if let Some(direction) = x_direction {
-                        s.spawn(async move {
-                            let mut grid_mesh = Mesh::new(
-                                PrimitiveTopology::TriangleList,
-                                RenderAssetUsages::RENDER_WORLD,
-                            );
-                            let mut vertices = Vec::new();
-                            let mut uvs = Vec::new();
-                            let mut indices = Vec::new();
-                            let mut normals = Vec::new();
-                            let mut indices_count = 0;
-
-                            for y in 0..CHUNK_SIZE {
-                                let (new_vertices, uv, index, normal) = create_terrain_edge_mesh(
-                                    [
-                                        (chunk_x * CHUNK_SIZE) + x_offset,
-                                        (chunk_y * CHUNK_SIZE) + y,
-                                    ],
-                                    heightmap_ref,
-                                    direction,
-                                    indices_count,
-                                );
-                                indices_count += new_vertices.len() as u32;
-                                vertices.extend(new_vertices);
-                                uvs.extend(uv);
-                                indices.extend(index);
-                                normals.extend(normal);
-                            }
-
-                            grid_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-                            grid_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-                            grid_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
-
-                            grid_mesh.insert_indices(Indices::U32(indices));
-
-                            grid_mesh
-                        });
-                    }
-                    if let Some(direction) = y_direction {
-                        s.spawn(async move {
-                            let mut grid_mesh = Mesh::new(
-                                PrimitiveTopology::TriangleList,
-                                RenderAssetUsages::RENDER_WORLD,
-                            );
-                            let mut vertices = Vec::new();
-                            let mut uvs = Vec::new();
-                            let mut indices = Vec::new();
-                            let mut normals = Vec::new();
-                            let mut indices_count = 0;
-
-                            for x in 0..CHUNK_SIZE {
-                                let (new_vertices, uv, index, normal) = create_terrain_edge_mesh(
-                                    [
-                                        (chunk_x * CHUNK_SIZE) + x,
-                                        (chunk_y * CHUNK_SIZE) + y_offset,
-                                    ],
-                                    heightmap_ref,
-                                    direction,
-                                    indices_count,
-                                );
-                                indices_count += new_vertices.len() as u32;
-                                vertices.extend(new_vertices);
-                                uvs.extend(uv);
-                                indices.extend(index);
-                                normals.extend(normal);
-                            }
-
-                            grid_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-                            grid_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-                            grid_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
-
-                            grid_mesh.insert_indices(Indices::U32(indices));
-
-                            grid_mesh
-                        });
-                    }
-                }
-            }
-        });
-        let edge_mesh = results.into_iter().reduce(|mut mesh_a, mesh_b| {
-            mesh_a.merge(mesh_b);
-            mesh_a
-        });
-        let mesh = mesh_assets.add(edge_mesh.unwrap());
-        let material = terrain_texture_atlas.handle.clone();
+    });
+    let material = terrain_texture_atlas.handle.clone();
+    for (chunk_position, edge_mesh) in results {
         commands
-            .spawn(PbrBundle {
-                mesh,
-                material,
+            .spawn(MaterialMeshBundle {
+                mesh: mesh_assets.add(edge_mesh),
+                material: material.clone(),
                 ..Default::default()
             })
             .insert(WorldMesh)
+            .insert(EdgeMesh)
+            .insert(ChunkPosition(chunk_position))
             .insert(WorldEntity);
-        println!("World mesh generation took: {:?}", start_time.elapsed());
     }
+    println!("Edge mesh generation took: {:?}", start_time.elapsed());
+}
+
+fn on_world_edge(chunk_position: [u32; 2], world_size: WorldSize) -> bool {
+    chunk_position[0] == 0
+        || chunk_position[1] == 0
+        || chunk_position[0] == world_size[0] - 1
+        || chunk_position[1] == world_size[1] - 1
+}
+
+//The walls along the sides of the chunk that are on the edge of the map, corner chunks have two
+fn create_chunk_edge_mesh(
+    chunk_position: [u32; 2],
+    heightmap: &Heightmap,
+    world_size: WorldSize,
+) -> Mesh {
+    let [chunk_x, chunk_y] = chunk_position;
+    let mut x_offset = 0;
+    let mut y_offset = 0;
+    let x_direction = match chunk_x {
+        0 => Some(FaceDirection::East),
+        x if x == world_size[0] - 1 => {
+            x_offset = CHUNK_SIZE - 1;
+            Some(FaceDirection::West)
+        }
+        _ => None,
+    };
+    let y_direction = match chunk_y {
+        0 => Some(FaceDirection::South),
+        y if y == world_size[1] - 1 => {
+            y_offset = CHUNK_SIZE - 1;
+            Some(FaceDirection::North)
+        }
+        _ => None,
+    };
+    let mut grid_mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    let mut vertices = Vec::new();
+    let mut weights = Vec::new();
+    let mut indices = Vec::new();
+    let mut normals = Vec::new();
+    let mut indices_count = 0;
+
+    let x_wall = x_direction.into_iter().flat_map(|direction| {
+        (0..CHUNK_SIZE).map(move |y| {
+            (
+                [
+                    (chunk_x * CHUNK_SIZE) + x_offset,
+                    (chunk_y * CHUNK_SIZE) + y,
+                ],
+                direction,
+            )
+        })
+    });
+    let y_wall = y_direction.into_iter().flat_map(|direction| {
+        (0..CHUNK_SIZE).map(move |x| {
+            (
+                [
+                    (chunk_x * CHUNK_SIZE) + x,
+                    (chunk_y * CHUNK_SIZE) + y_offset,
+                ],
+                direction,
+            )
+        })
+    });
+    for (starting_position, direction) in x_wall.chain(y_wall) {
+        let (new_vertices, weight, index, normal) =
+            create_terrain_edge_mesh(starting_position, heightmap, direction, indices_count);
+        indices_count += new_vertices.len() as u32;
+        vertices.extend(new_vertices);
+        weights.extend(weight);
+        indices.extend(index);
+        normals.extend(normal);
+    }
+
+    grid_mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, weights);
+    grid_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    grid_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+
+    grid_mesh.insert_indices(Indices::U32(indices));
+
+    grid_mesh
+}
+
+//Positions, terrain weights, indices and normals
+pub type MeshVecs = (Vec<[f32; 3]>, Vec<[f32; 4]>, Vec<u32>, Vec<[f32; 3]>);
+
+//The whole terrain surface as one mesh, without chunks or edges, for exporting to other tools
+pub fn create_world_mesh_vecs(
+    heightmap: &Heightmap,
+    world_settings: &WorldSettings,
+    lod: u32,
+) -> MeshVecs {
+    let [width, height] = world_settings.tile_world_size();
+    create_terrain_grid(
+        heightmap,
+        world_settings.seed(),
+        [0, 0],
+        [&grid_offsets(width, lod), &grid_offsets(height, lod)],
+    )
+}
+
+//How many tiles each quad covers at the LOD level
+pub fn lod_step(lod: u32) -> u32 {
+    if lod == 0 {
+        1
+    } else {
+        lod * 2
+    }
+}
+
+//Where the vertices along a side go, the last quad is cut short so the grid always ends on the side's end
+fn grid_offsets(length: u32, step: u32) -> Vec<u32> {
+    (0..length)
+        .step_by(step as usize)
+        .chain(std::iter::once(length))
+        .collect()
+}
+
+//Every LOD ends on the chunk border
+fn edge_offsets(step: u32) -> Vec<u32> {
+    grid_offsets(CHUNK_SIZE, step)
+}
+
+//Smooth normal from the neighbouring heightmap points, every LOD uses the full heightmap so the shading doesn't jump between LODs
+fn vertex_normal(heightmap: &Heightmap, [x, y]: [u32; 2]) -> [f32; 3] {
+    let [width, height] = heightmap.size();
+    let height_at = |point: [u32; 2]| heightmap[point] * WORLD_HEIGHT_SCALE;
+    let [left, right] = [x.saturating_sub(1), (x + 1).min(width - 1)];
+    let [down, up] = [y.saturating_sub(1), (y + 1).min(height - 1)];
+    let slope_x =
+        (height_at([right, y]) - height_at([left, y])) / ((right - left) as f32 * TILE_SIZE);
+    let slope_y = (height_at([x, up]) - height_at([x, down])) / ((up - down) as f32 * TILE_SIZE);
+    Vec3::new(-slope_x, 1.0, -slope_y).normalize().to_array()
+}
+
+//Position, terrain weights and normal of the vertex on the heightmap point
+//The terrain type is seeded by the position, so a vertex shared by chunks or LODs always gets the same type
+fn terrain_vertex(
+    heightmap: &Heightmap,
+    seed: u32,
+    point: [u32; 2],
+) -> ([f32; 3], [f32; 4], [f32; 3]) {
+    let height = heightmap[point] * WORLD_HEIGHT_SCALE;
+    let normal = vertex_normal(heightmap, point);
+    let steepness_angle = Vec3::from_array(normal).dot(Vec3::Y).acos().to_degrees();
+    let mut rng =
+        StdRng::seed_from_u64(((seed as u64) << 32) | ((point[0] as u64) << 16) | point[1] as u64);
+    let terrain_type = get_terrain_type(height, steepness_angle, &mut rng);
+    (
+        [point[0] as f32, height, point[1] as f32],
+        terrain_weights(terrain_type),
+        normal,
+    )
+}
+
+//A grid of vertices shared between the quads around them, the offsets along each axis are from the origin
+fn create_terrain_grid(
+    heightmap: &Heightmap,
+    seed: u32,
+    origin: [u32; 2],
+    offsets: [&[u32]; 2],
+) -> MeshVecs {
+    let mut vertices = Vec::new();
+    let mut weights = Vec::new();
+    let mut indices = Vec::new();
+    let mut normals = Vec::new();
+    for &y in offsets[1] {
+        for &x in offsets[0] {
+            let (vertex, weight, normal) =
+                terrain_vertex(heightmap, seed, [origin[0] + x, origin[1] + y]);
+            vertices.push(vertex);
+            weights.push(weight);
+            normals.push(normal);
+        }
+    }
+    let row_length = offsets[0].len() as u32;
+    for y in 0..offsets[1].len() as u32 - 1 {
+        for x in 0..row_length - 1 {
+            let vert_0 = y * row_length + x;
+            let vert_1 = vert_0 + 1;
+            let vert_3 = vert_0 + row_length;
+            let vert_2 = vert_3 + 1;
+            indices.extend([vert_2, vert_1, vert_0, vert_0, vert_3, vert_2]);
+        }
+    }
+    (vertices, weights, indices, normals)
+}
+
+fn create_chunk_mesh(chunk_position: [u32; 2], heightmap: &Heightmap, seed: u32, lod: u32) -> Mesh {
+    let mut grid_mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    let offsets = edge_offsets(lod_step(lod));
+    let (mut vertices, mut weights, mut indices, mut normals) = create_terrain_grid(
+        heightmap,
+        seed,
+        [
+            chunk_position[0] * CHUNK_SIZE,
+            chunk_position[1] * CHUNK_SIZE,
+        ],
+        [&offsets, &offsets],
+    );
+    let (new_vertices, weight, index, normal) =
+        create_chunk_skirts(chunk_position, heightmap, seed, vertices.len() as u32, lod);
+    vertices.extend(new_vertices);
+    weights.extend(weight);
+    indices.extend(index);
+    normals.extend(normal);
+
+    grid_mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, weights);
+    grid_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    grid_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+
+    grid_mesh.insert_indices(Indices::U32(indices));
+    grid_mesh
+}
+
+//The sides of the chunk in chunk coordinates, walked around the chunk so the skirts all face outwards
+fn chunk_sides(step: u32) -> [Vec<[u32; 2]>; 4] {
+    let offsets = edge_offsets(step);
+    let reversed = offsets.iter().rev().copied().collect_vec();
+    [
+        offsets.iter().map(|&y| [0, y]).collect(),
+        offsets.iter().map(|&x| [x, CHUNK_SIZE]).collect(),
+        reversed.iter().map(|&y| [CHUNK_SIZE, y]).collect(),
+        reversed.iter().map(|&x| [x, 0]).collect(),
+    ]
+}
+
+//The lowest the border can be near the segment, whatever LOD the neighbouring chunk is at
+//Neighbouring chunks share the border vertices at the chunk corners, so any quad of theirs that touches
+//the segment lies within the largest LOD step of it, and can't dip below the lowest heightmap point there
+fn skirt_bottom(heightmap: &Heightmap, chunk_position: [u32; 2], segment: [[u32; 2]; 2]) -> f32 {
+    let max_step = lod_step(LOD_LEVELS);
+    let axis = if segment[0][0] == segment[1][0] { 1 } else { 0 };
+    let start = segment[0][axis]
+        .min(segment[1][axis])
+        .saturating_sub(max_step);
+    let end = (segment[0][axis].max(segment[1][axis]) + max_step).min(CHUNK_SIZE);
+    let lowest = (start..=end)
+        .map(|offset| {
+            let mut point = segment[0];
+            point[axis] = offset;
+            heightmap[[
+                (chunk_position[0] * CHUNK_SIZE) + point[0],
+                (chunk_position[1] * CHUNK_SIZE) + point[1],
+            ]]
+        })
+        .fold(f32::INFINITY, f32::min);
+    lowest * WORLD_HEIGHT_SCALE - SKIRT_DEPTH
+}
+
+//Walls hanging down from the chunk border that fill the cracks between chunks at different LODs
+//They are shaded like the border vertices they hang from, so they blend in with the surface around the crack
+fn create_chunk_skirts(
+    chunk_position: [u32; 2],
+    heightmap: &Heightmap,
+    seed: u32,
+    indices_count: u32,
+    lod: u32,
+) -> MeshVecs {
+    let mut vertices = Vec::new();
+    let mut weights = Vec::new();
+    let mut indices = Vec::new();
+    let mut normals = Vec::new();
+    let mut indices_count = indices_count;
+    for side in chunk_sides(lod_step(lod)) {
+        for (&start, &end) in side.iter().tuple_windows() {
+            let bottom = skirt_bottom(heightmap, chunk_position, [start, end]);
+            let [start, end] = [start, end].map(|point| {
+                terrain_vertex(
+                    heightmap,
+                    seed,
+                    [
+                        (chunk_position[0] * CHUNK_SIZE) + point[0],
+                        (chunk_position[1] * CHUNK_SIZE) + point[1],
+                    ],
+                )
+            });
+            vertices.extend([
+                start.0,
+                end.0,
+                [end.0[0], bottom, end.0[2]],
+                [start.0[0], bottom, start.0[2]],
+            ]);
+            weights.extend([start.1, end.1, end.1, start.1]);
+            normals.extend([start.2, end.2, end.2, start.2]);
+            indices.extend([
+                indices_count,
+                indices_count + 3,
+                indices_count + 1,
+                indices_count + 1,
+                indices_count + 3,
+                indices_count + 2,
+            ]);
+            indices_count += 4;
+        }
+    }
+    (vertices, weights, indices, normals)
 }
 
-type MeshVecs = (Vec<[f32; 3]>, Vec<[f32; 2]>, Vec<u32>, Vec<[f32; 3]>);
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum FaceDirection {
     North,
@@ -333,7 +456,7 @@ fn create_terrain_edge_mesh(
     indices_count: u32,
 ) -> MeshVecs {
     let mut vertices_ = Vec::new();
-    let mut uvs_ = Vec::new();
+    let mut weights_ = Vec::new();
     let mut indices_ = Vec::new();
     let mut normals_ = Vec::new();
     let mut indices_count = indices_count;
@@ -419,89 +542,15 @@ fn create_terrain_edge_mesh(
             }
         };
 
-        let uv = get_terrain_texture_uv(TerrainType::Dirt).to_vec();
+        let weights = vec![terrain_weights(TerrainType::Dirt); 4];
         indices_count += vertices.len() as u32;
 
         vertices_.extend(vertices);
-        uvs_.extend(uv);
+        weights_.extend(weights);
         indices_.extend(indices);
         normals_.extend(normals);
     }
-    (vertices_, uvs_, indices_, normals_)
-}
-
-fn create_terrain_mesh(
-    starting_position: [u32; 2],
-    heightmap: &Heightmap,
-    rng: &mut StdRng,
-    indices_count: u32,
-    lod: u32,
-) -> MeshVecs {
-    let tile_size = TILE_SIZE * lod as f32;
-    let height = heightmap[starting_position] * WORLD_HEIGHT_SCALE;
-    let mut average_height = height;
-    let vert_0 = [
-        starting_position[0] as f32,
-        height,
-        starting_position[1] as f32,
-    ];
-    let height = heightmap[[
-        (starting_position[0] + lod).clamp(0, heightmap.size()[0]),
-        starting_position[1],
-    ]] * WORLD_HEIGHT_SCALE;
-    average_height += height;
-    let vert_1 = [
-        starting_position[0] as f32 + tile_size,
-        height,
-        starting_position[1] as f32,
-    ];
-    let height = heightmap[[
-        (starting_position[0] + lod).clamp(0, heightmap.size()[0]),
-        (starting_position[1] + lod).clamp(0, heightmap.size()[1]),
-    ]] * WORLD_HEIGHT_SCALE;
-    average_height += height;
-    let vert_2 = [
-        starting_position[0] as f32 + tile_size,
-        height,
-        starting_position[1] as f32 + tile_size,
-    ];
-    let height = heightmap[[
-        starting_position[0],
-        (starting_position[1] + lod).clamp(0, heightmap.size()[1]),
-    ]] * WORLD_HEIGHT_SCALE;
-    average_height += height;
-    let vert_3 = [
-        starting_position[0] as f32,
-        height,
-        starting_position[1] as f32 + tile_size * TILE_SIZE,
-    ];
-    average_height /= 4.0;
-    let vertices = vec![vert_0, vert_1, vert_2, vert_3];
-
-    let indices = vec![
-        indices_count + 2,
-        indices_count + 1,
-        indices_count,
-        indices_count,
-        indices_count + 3,
-        indices_count + 2,
-    ];
-    let normal = unnormalized_normal_array(vert_0, vert_3, vert_1)
-        .normalize()
-        .to_array();
-    let normals = vec![normal, normal, normal, normal];
-
-    let steepness_angle = Into::<Vec3>::into(normal)
-        .normalize()
-        .dot(Vec3::new(0.0, 1.0, 0.0))
-        .acos()
-        .to_degrees();
-
-    let terrain_type = get_terrain_type(average_height, steepness_angle, rng);
-
-    let uv = get_terrain_texture_uv(terrain_type).to_vec();
-
-    (vertices, uv, indices, normals)
+    (vertices_, weights_, indices_, normals_)
 }
 
 fn get_terrain_type(height: f32, steepness_angle: f32, rng: &mut StdRng) -> TerrainType {
@@ -537,6 +586,67 @@ fn get_terrain_type(height: f32, steepness_angle: f32, rng: &mut StdRng) -> Terr
     terrain_type
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Height along the border between the first two chunks when its vertices are the step apart
+    fn border_height(heightmap: &Heightmap, step: u32, offset: u32) -> f32 {
+        let offsets = edge_offsets(step);
+        let (&start, &end) = offsets
+            .iter()
+            .tuple_windows()
+            .find(|&(_, &end)| end >= offset)
+            .unwrap();
+        let heights = [start, end].map(|y| heightmap[[CHUNK_SIZE, y]] * WORLD_HEIGHT_SCALE);
+        let t = (offset - start) as f32 / (end - start) as f32;
+        heights[0] + (heights[1] - heights[0]) * t
+    }
+
+    #[test]
+    fn neighbouring_chunks_share_their_border_vertices() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut heightmap = Heightmap::new([2, 1]);
+        for height in heightmap.data.iter_mut() {
+            *height = rng.gen_range(0.0..1.0);
+        }
+        let offsets = edge_offsets(lod_step(2));
+        let left = create_terrain_grid(&heightmap, 7, [0, 0], [&offsets, &offsets]);
+        let right = create_terrain_grid(&heightmap, 7, [CHUNK_SIZE, 0], [&offsets, &offsets]);
+        let row_length = offsets.len();
+        assert_eq!(left.0.len(), row_length * row_length);
+        assert_eq!(left.2.len(), (row_length - 1) * (row_length - 1) * 6);
+        for row in 0..row_length {
+            let left_border = row * row_length + row_length - 1;
+            let right_border = row * row_length;
+            assert_eq!(left.0[left_border], right.0[right_border]);
+            assert_eq!(left.1[left_border], right.1[right_border]);
+            assert_eq!(left.3[left_border], right.3[right_border]);
+        }
+    }
+
+    #[test]
+    fn skirts_reach_below_every_neighbouring_lod() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut heightmap = Heightmap::new([2, 1]);
+        for height in heightmap.data.iter_mut() {
+            *height = rng.gen_range(0.0..1.0);
+        }
+        for lod in 0..=LOD_LEVELS {
+            for (&start, &end) in edge_offsets(lod_step(lod)).iter().tuple_windows() {
+                let segment = [[CHUNK_SIZE, start], [CHUNK_SIZE, end]];
+                let bottom = skirt_bottom(&heightmap, [0, 0], segment);
+                for neighbour_lod in 0..=LOD_LEVELS {
+                    for offset in start..=end {
+                        let height = border_height(&heightmap, lod_step(neighbour_lod), offset);
+                        assert!(bottom < height);
+                    }
+                }
+            }
+        }
+    }
+}
+
 /* pub fn generate_tree_mesh(
     mut commands: Commands,
     tree_mesh_query: Query<Entity, With<WorldMesh>>,
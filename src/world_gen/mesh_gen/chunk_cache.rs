@@ -0,0 +1,387 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use bevy::{
+    prelude::*,
+    render::primitives::Frustum,
+    tasks::{block_on, AsyncComputeTaskPool, Task},
+};
+use smooth_bevy_cameras::{controllers::orbit::OrbitCameraController, LookTransform};
+
+use crate::{
+    assets::TerrainTextureAtlas,
+    world::{WorldEntity, WorldSize},
+    world_gen::{
+        consts::{CHUNK_SIZE, LOD_LEVELS},
+        heightmap::{edit::HeightmapEdit, Heightmap},
+        WorldSettings,
+    },
+};
+
+use super::{create_chunk_mesh, culling::ChunkCulling, ChunkPosition, LODLevel, WorldMesh};
+
+//Hidden chunk meshes kept around in case the camera comes back, the least recently shown are despawned first
+const MAX_HIDDEN_CHUNK_MESHES: usize = 256;
+
+//A chunk and one of its LOD levels
+type ChunkMeshKey = ([u32; 2], u32);
+
+struct CachedChunkMesh {
+    entity: Entity,
+    //The frame the mesh was last shown on
+    last_shown: u64,
+    //The heightmap changed under the mesh, it is still shown until its replacement is ready
+    outdated: bool,
+}
+
+//Chunk meshes are only generated once the camera is close enough to need their LOD
+#[derive(Resource, Default)]
+pub struct ChunkMeshes {
+    meshes: HashMap<ChunkMeshKey, CachedChunkMesh>,
+    tasks: HashMap<ChunkMeshKey, Task<Mesh>>,
+    //Shared by every task, so they don't each need a copy of the heightmap
+    heightmap: Option<Arc<Heightmap>>,
+    //A hash of the heights under each chunk, so a heightmap change only remakes the chunks it touched
+    chunk_hashes: HashMap<[u32; 2], u64>,
+    culling: Option<ChunkCulling>,
+    //The LOD each chunk in view is shown at, every other cached mesh is hidden
+    shown: HashMap<[u32; 2], u32>,
+    frame: u64,
+}
+
+impl ChunkMeshes {
+    //Marks the meshes of every chunk the heightmap changed under as outdated, and drops chunks that are off the map
+    fn update_heightmap(
+        &mut self,
+        commands: &mut Commands,
+        heightmap: &Heightmap,
+        world_size: WorldSize,
+    ) {
+        let chunk_hashes = chunk_hashes(heightmap, world_size);
+        let changed = |chunk: &[u32; 2]| self.chunk_hashes.get(chunk) != chunk_hashes.get(chunk);
+        //Dropping a task cancels it
+        self.tasks.retain(|(chunk, _), _| !changed(chunk));
+        self.meshes.retain(|(chunk, _), cached| {
+            if !chunk_hashes.contains_key(chunk) {
+                commands.entity(cached.entity).despawn();
+                return false;
+            }
+            cached.outdated |= changed(chunk);
+            true
+        });
+        self.shown
+            .retain(|chunk, _| chunk_hashes.contains_key(chunk));
+        self.chunk_hashes = chunk_hashes;
+        self.culling = Some(ChunkCulling::new(heightmap, world_size));
+        self.heightmap = Some(Arc::new(heightmap.clone()));
+    }
+    //Marks the meshes of the chunks under the edits as outdated, without going over the rest of the heightmap
+    //Returns false if the heightmap was resized, then the whole cache has to be updated instead
+    fn apply_edits(
+        &mut self,
+        heightmap: &Heightmap,
+        world_size: WorldSize,
+        edits: &[HeightmapEdit],
+    ) -> bool {
+        let Some(snapshot) = self
+            .heightmap
+            .as_mut()
+            .filter(|snapshot| snapshot.size() == heightmap.size())
+        else {
+            return false;
+        };
+        //Only copied if a task is still using the old heightmap
+        let snapshot = Arc::make_mut(snapshot);
+        let mut edited = Vec::new();
+        for edit in edits {
+            for point in edit.points(heightmap.size()) {
+                snapshot[point] = heightmap[point];
+            }
+            edited.extend(edit.chunks(world_size));
+        }
+        edited.sort_unstable();
+        edited.dedup();
+        //An edit that put back the heights that were there doesn't need new meshes
+        edited.retain(|&chunk| {
+            let hash = chunk_hash(heightmap, chunk);
+            self.chunk_hashes.insert(chunk, hash) != Some(hash)
+        });
+        self.tasks.retain(|(chunk, _), _| !edited.contains(chunk));
+        for chunk in &edited {
+            for lod in 0..=LOD_LEVELS {
+                if let Some(cached) = self.meshes.get_mut(&(*chunk, lod)) {
+                    cached.outdated = true;
+                }
+            }
+        }
+        if let Some(culling) = &mut self.culling {
+            culling.update_chunks(heightmap, &edited);
+        }
+        true
+    }
+    fn request(&mut self, key: ChunkMeshKey, seed: u32) {
+        if self.tasks.contains_key(&key) {
+            return;
+        }
+        let Some(heightmap) = self.heightmap.clone() else {
+            return;
+        };
+        let (chunk, lod) = key;
+        let task = AsyncComputeTaskPool::get()
+            .spawn(async move { create_chunk_mesh(chunk, &heightmap, seed, lod) });
+        self.tasks.insert(key, task);
+    }
+    //The mesh to show while the wanted LOD is being generated, the nearest LOD that is already cached
+    fn fallback(&self, chunk: [u32; 2], lod: u32) -> Option<u32> {
+        (0..=LOD_LEVELS)
+            .filter(|&cached_lod| self.meshes.contains_key(&(chunk, cached_lod)))
+            .min_by_key(|&cached_lod| cached_lod.abs_diff(lod))
+    }
+}
+
+fn chunk_hash(heightmap: &Heightmap, [chunk_x, chunk_y]: [u32; 2]) -> u64 {
+    //Chunk meshes reach the first row of the next chunk, and the normals and terrain types of their border
+    //vertices come from the points on either side of the border
+    let [width, height] = heightmap.size();
+    let mut hasher = DefaultHasher::new();
+    for x in
+        (chunk_x * CHUNK_SIZE).saturating_sub(1)..=((chunk_x + 1) * CHUNK_SIZE + 1).min(width - 1)
+    {
+        for y in (chunk_y * CHUNK_SIZE).saturating_sub(1)
+            ..=((chunk_y + 1) * CHUNK_SIZE + 1).min(height - 1)
+        {
+            heightmap[[x, y]].to_bits().hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+fn chunk_hashes(heightmap: &Heightmap, world_size: WorldSize) -> HashMap<[u32; 2], u64> {
+    let mut chunk_hashes = HashMap::new();
+    for chunk_x in 0..world_size[0] {
+        for chunk_y in 0..world_size[1] {
+            let chunk = [chunk_x, chunk_y];
+            chunk_hashes.insert(chunk, chunk_hash(heightmap, chunk));
+        }
+    }
+    chunk_hashes
+}
+
+//The LOD level the chunk should be shown at, by how far it is from the camera and what it is looking at
+fn wanted_lod(chunk: [u32; 2], transform: &LookTransform) -> u32 {
+    //Convert chunk position to world position
+    let chunk_position = Vec2::new(
+        (chunk[0] as f32 * CHUNK_SIZE as f32) + CHUNK_SIZE as f32 / 2.0,
+        (chunk[1] as f32 * CHUNK_SIZE as f32) + CHUNK_SIZE as f32 / 2.0,
+    );
+    let camera_distance =
+        ((transform.eye.xz().distance(chunk_position) / CHUNK_SIZE as f32).round() as u32)
+            .clamp(1, LOD_LEVELS);
+    let target_distance = ((transform.target.xz().distance(chunk_position) / CHUNK_SIZE as f32)
+        .round() as u32)
+        .clamp(1, LOD_LEVELS);
+    camera_distance.min(target_distance)
+}
+
+pub fn init_chunk_meshes(mut commands: Commands) {
+    commands.init_resource::<ChunkMeshes>();
+}
+
+//The chunk mesh entities are despawned with the rest of the world
+pub fn exit_chunk_meshes(mut commands: Commands) {
+    commands.remove_resource::<ChunkMeshes>();
+}
+
+//Keeps the cache in step with the heightmap, and spawns the chunk meshes that finished generating
+//Edits only remake the chunks under them, any other heightmap change is checked chunk by chunk
+pub fn update_chunk_meshes(
+    mut commands: Commands,
+    mut chunk_meshes: ResMut<ChunkMeshes>,
+    heightmap: Res<Heightmap>,
+    world_settings: Res<WorldSettings>,
+    mut heightmap_edits: EventReader<HeightmapEdit>,
+    mut mesh_assets: ResMut<Assets<Mesh>>,
+    terrain_texture_atlas: Res<TerrainTextureAtlas>,
+) {
+    let world_size = world_settings.world_size();
+    let edits = heightmap_edits.read().copied().collect::<Vec<_>>();
+    let full_update = if edits.is_empty() {
+        heightmap.is_changed() || chunk_meshes.heightmap.is_none()
+    } else {
+        !chunk_meshes.apply_edits(&heightmap, world_size, &edits)
+    };
+    if full_update {
+        chunk_meshes.update_heightmap(&mut commands, &heightmap, world_size);
+    }
+    let finished = chunk_meshes
+        .tasks
+        .iter()
+        .filter(|(_, task)| task.is_finished())
+        .map(|(key, _)| *key)
+        .collect::<Vec<_>>();
+    for key in finished {
+        let mesh = block_on(chunk_meshes.tasks.remove(&key).unwrap());
+        let (chunk, lod) = key;
+        let entity = commands
+            .spawn(MaterialMeshBundle {
+                mesh: mesh_assets.add(mesh),
+                material: terrain_texture_atlas.handle.clone(),
+                visibility: Visibility::Hidden,
+                ..Default::default()
+            })
+            .insert(WorldMesh)
+            .insert(WorldEntity)
+            .insert(LODLevel(lod))
+            .insert(ChunkPosition(chunk))
+            .id();
+        let frame = chunk_meshes.frame;
+        let cached = CachedChunkMesh {
+            entity,
+            last_shown: frame,
+            outdated: false,
+        };
+        if let Some(replaced) = chunk_meshes.meshes.insert(key, cached) {
+            commands.entity(replaced.entity).despawn();
+        }
+    }
+}
+
+fn set_visibility(visibilities: &mut Query<&mut Visibility>, entity: Entity, new: Visibility) {
+    if let Ok(mut visibility) = visibilities.get_mut(entity) {
+        //Only written when it changes, so unchanged chunks don't trigger change detection
+        if *visibility != new {
+            *visibility = new;
+        }
+    }
+}
+
+//Shows the chunks in view at the LOD they need, generating the meshes that aren't cached yet
+//Chunks out of view or hidden behind the terrain are skipped, so the work each frame grows with what can be seen
+pub fn level_of_detail(
+    mut commands: Commands,
+    mut chunk_meshes: ResMut<ChunkMeshes>,
+    world_settings: Res<WorldSettings>,
+    cameras: Query<(&OrbitCameraController, &LookTransform, &Frustum)>,
+    mut visibilities: Query<&mut Visibility>,
+) {
+    let Some((_, transform, frustum)) = cameras.iter().find(|c| c.0.enabled) else {
+        return;
+    };
+    let Some(visible) = chunk_meshes
+        .culling
+        .as_ref()
+        .map(|culling| culling.visible_chunks(frustum, transform.eye))
+    else {
+        return;
+    };
+    chunk_meshes.frame += 1;
+    let frame = chunk_meshes.frame;
+    let in_view = visible.iter().copied().collect::<HashSet<_>>();
+    let out_of_view = chunk_meshes
+        .shown
+        .keys()
+        .filter(|chunk| !in_view.contains(*chunk))
+        .copied()
+        .collect::<Vec<_>>();
+    for chunk in out_of_view {
+        let lod = chunk_meshes.shown.remove(&chunk).unwrap();
+        if let Some(cached) = chunk_meshes.meshes.get(&(chunk, lod)) {
+            set_visibility(&mut visibilities, cached.entity, Visibility::Hidden);
+        }
+    }
+    for chunk in visible {
+        let lod = wanted_lod(chunk, transform);
+        let up_to_date = chunk_meshes
+            .meshes
+            .get(&(chunk, lod))
+            .is_some_and(|cached| !cached.outdated);
+        if !up_to_date {
+            chunk_meshes.request((chunk, lod), world_settings.seed());
+        }
+        let Some(shown) = chunk_meshes.fallback(chunk, lod) else {
+            continue;
+        };
+        let previous = chunk_meshes.shown.insert(chunk, shown);
+        if let Some(previous) = previous.filter(|&previous| previous != shown) {
+            if let Some(cached) = chunk_meshes.meshes.get(&(chunk, previous)) {
+                set_visibility(&mut visibilities, cached.entity, Visibility::Hidden);
+            }
+        }
+        //Set every frame, since a mesh remade after a heightmap change starts out hidden
+        let cached = chunk_meshes.meshes.get_mut(&(chunk, shown)).unwrap();
+        cached.last_shown = frame;
+        set_visibility(&mut visibilities, cached.entity, Visibility::Visible);
+    }
+    //Evict the hidden meshes that were shown the longest time ago
+    if chunk_meshes
+        .meshes
+        .len()
+        .saturating_sub(chunk_meshes.shown.len())
+        <= MAX_HIDDEN_CHUNK_MESHES
+    {
+        return;
+    }
+    let mut hidden = chunk_meshes
+        .meshes
+        .iter()
+        .filter(|(_, cached)| cached.last_shown != frame)
+        .map(|(key, cached)| (*key, cached.last_shown))
+        .collect::<Vec<_>>();
+    if hidden.len() > MAX_HIDDEN_CHUNK_MESHES {
+        hidden.sort_unstable_by_key(|(_, last_shown)| *last_shown);
+        for (key, _) in &hidden[..hidden.len() - MAX_HIDDEN_CHUNK_MESHES] {
+            let evicted = chunk_meshes.meshes.remove(key).unwrap();
+            commands.entity(evicted.entity).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_only_touch_the_chunks_under_them() {
+        let mut heightmap = Heightmap::new([3, 2]);
+        let before = chunk_hashes(&heightmap, [3, 2]);
+        //On the border between the first two chunks
+        heightmap[[CHUNK_SIZE, 5]] = 0.5;
+        let after = chunk_hashes(&heightmap, [3, 2]);
+        let changed = before
+            .keys()
+            .filter(|chunk| before[*chunk] != after[*chunk])
+            .count();
+        assert_eq!(changed, 2);
+        assert!(before[&[0, 0]] != after[&[0, 0]]);
+        assert!(before[&[1, 0]] != after[&[1, 0]]);
+    }
+
+    #[test]
+    fn edits_next_to_a_border_outdate_both_chunks() {
+        let mut heightmap = Heightmap::new([3, 1]);
+        let mut chunk_meshes = ChunkMeshes {
+            heightmap: Some(Arc::new(heightmap.clone())),
+            chunk_hashes: chunk_hashes(&heightmap, [3, 1]),
+            ..Default::default()
+        };
+        for chunk_x in 0..3 {
+            let cached = CachedChunkMesh {
+                entity: Entity::from_raw(chunk_x),
+                last_shown: 0,
+                outdated: false,
+            };
+            chunk_meshes.meshes.insert(([chunk_x, 0], 1), cached);
+        }
+        //The normals of the border vertices of the first chunk change
+        let point = [CHUNK_SIZE + 1, 5];
+        heightmap[point] = 0.5;
+        assert!(chunk_meshes.apply_edits(&heightmap, [3, 1], &[HeightmapEdit::new(point, point)]));
+        assert!(chunk_meshes.meshes[&([0, 0], 1)].outdated);
+        assert!(chunk_meshes.meshes[&([1, 0], 1)].outdated);
+        assert!(!chunk_meshes.meshes[&([2, 0], 1)].outdated);
+        assert!(chunk_meshes.heightmap.as_ref().unwrap()[point] == 0.5);
+    }
+}
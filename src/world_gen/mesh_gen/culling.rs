@@ -0,0 +1,287 @@
+use bevy::{
+    math::Affine3A,
+    prelude::*,
+    render::primitives::{Aabb, Frustum},
+};
+
+use crate::{
+    world::WorldSize,
+    world_gen::{
+        consts::{CHUNK_SIZE, WORLD_HEIGHT_SCALE},
+        heightmap::Heightmap,
+    },
+};
+
+use super::SKIRT_DEPTH;
+
+//Chunks are tested against the view in blocks of this many chunks a side first, so blocks out of view are skipped at once
+const CULLING_BLOCK_SIZE: u32 = 4;
+//Chunks just outside the view still count as visible, so their meshes are ready by the time they come into view
+const CULLING_MARGIN: f32 = CHUNK_SIZE as f32 / 2.0;
+//How far apart the sight line to a chunk is sampled when looking for the chunks in front of it
+const OCCLUSION_STEP: f32 = CHUNK_SIZE as f32 / 2.0;
+
+//A value for every chunk laid out like the heightmap, so finding a chunk's value is just indexing
+#[derive(Clone, Debug)]
+pub struct ChunkGrid<T> {
+    size: WorldSize,
+    cells: Vec<T>,
+}
+
+impl<T> ChunkGrid<T> {
+    pub fn from_fn(size: WorldSize, mut value: impl FnMut([u32; 2]) -> T) -> Self {
+        let cells = (0..size[0])
+            .flat_map(|x| (0..size[1]).map(move |y| [x, y]))
+            .map(&mut value)
+            .collect();
+        Self { size, cells }
+    }
+    pub fn size(&self) -> WorldSize {
+        self.size
+    }
+    pub fn get(&self, [x, y]: [u32; 2]) -> Option<&T> {
+        if x >= self.size[0] || y >= self.size[1] {
+            return None;
+        }
+        self.cells.get((x * self.size[1] + y) as usize)
+    }
+    pub fn get_mut(&mut self, [x, y]: [u32; 2]) -> Option<&mut T> {
+        if x >= self.size[0] || y >= self.size[1] {
+            return None;
+        }
+        self.cells.get_mut((x * self.size[1] + y) as usize)
+    }
+}
+
+//The box the terrain surface of a chunk fits in, in world coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkBounds {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl ChunkBounds {
+    pub fn new(heightmap: &Heightmap, chunk: [u32; 2]) -> Self {
+        let start = [chunk[0] * CHUNK_SIZE, chunk[1] * CHUNK_SIZE];
+        let (mut lowest, mut highest) = (f32::INFINITY, f32::NEG_INFINITY);
+        //Chunk meshes reach the first row of the next chunk
+        for x in start[0]..=start[0] + CHUNK_SIZE {
+            for y in start[1]..=start[1] + CHUNK_SIZE {
+                lowest = lowest.min(heightmap[[x, y]]);
+                highest = highest.max(heightmap[[x, y]]);
+            }
+        }
+        Self {
+            min: Vec3::new(
+                start[0] as f32,
+                lowest * WORLD_HEIGHT_SCALE,
+                start[1] as f32,
+            ),
+            max: Vec3::new(
+                (start[0] + CHUNK_SIZE) as f32,
+                highest * WORLD_HEIGHT_SCALE,
+                (start[1] + CHUNK_SIZE) as f32,
+            ),
+        }
+    }
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+    //Grown by the margin, and down to the bottom of the skirts
+    fn culling_aabb(&self) -> Aabb {
+        Aabb::from_min_max(
+            self.min - Vec3::new(CULLING_MARGIN, CULLING_MARGIN + SKIRT_DEPTH, CULLING_MARGIN),
+            self.max + Vec3::splat(CULLING_MARGIN),
+        )
+    }
+    fn in_view(&self, frustum: &Frustum) -> bool {
+        frustum.intersects_obb(&self.culling_aabb(), &Affine3A::IDENTITY, true, true)
+    }
+    //Whether the sight line goes through the solid ground under the chunk's lowest point
+    //The sight line is straight, so it is lowest where it enters or leaves the chunk
+    fn blocks(&self, eye: Vec3, point: Vec3) -> bool {
+        let direction = point - eye;
+        let (mut enter, mut exit) = (0.0_f32, 1.0_f32);
+        for axis in [0, 2] {
+            if direction[axis].abs() < f32::EPSILON {
+                if eye[axis] < self.min[axis] || eye[axis] > self.max[axis] {
+                    return false;
+                }
+                continue;
+            }
+            let low = (self.min[axis] - eye[axis]) / direction[axis];
+            let high = (self.max[axis] - eye[axis]) / direction[axis];
+            enter = enter.max(low.min(high));
+            exit = exit.min(low.max(high));
+        }
+        enter < exit
+            && (eye.y + direction.y * enter < self.min.y || eye.y + direction.y * exit < self.min.y)
+    }
+}
+
+//The bounds of every chunk, and of the blocks of chunks they are tested in
+pub struct ChunkCulling {
+    chunks: ChunkGrid<ChunkBounds>,
+    blocks: ChunkGrid<ChunkBounds>,
+}
+
+impl ChunkCulling {
+    pub fn new(heightmap: &Heightmap, world_size: WorldSize) -> Self {
+        let chunks = ChunkGrid::from_fn(world_size, |chunk| ChunkBounds::new(heightmap, chunk));
+        let block_size = world_size.map(|size| size.div_ceil(CULLING_BLOCK_SIZE));
+        let blocks = ChunkGrid::from_fn(block_size, |block| block_bounds(&chunks, block));
+        Self { chunks, blocks }
+    }
+    //Remakes the bounds of the chunks the heightmap changed under, and of the blocks they are in
+    pub fn update_chunks(&mut self, heightmap: &Heightmap, chunks: &[[u32; 2]]) {
+        let mut blocks = Vec::new();
+        for &chunk in chunks {
+            if let Some(bounds) = self.chunks.get_mut(chunk) {
+                *bounds = ChunkBounds::new(heightmap, chunk);
+                blocks.push(chunk.map(|chunk| chunk / CULLING_BLOCK_SIZE));
+            }
+        }
+        blocks.sort_unstable();
+        blocks.dedup();
+        for block in blocks {
+            *self.blocks.get_mut(block).unwrap() = block_bounds(&self.chunks, block);
+        }
+    }
+    pub fn bounds(&self, chunk: [u32; 2]) -> Option<ChunkBounds> {
+        self.chunks.get(chunk).copied()
+    }
+    //Every chunk in or near the view that isn't hidden behind the terrain in front of it
+    pub fn visible_chunks(&self, frustum: &Frustum, eye: Vec3) -> Vec<[u32; 2]> {
+        let [width, height] = self.blocks.size();
+        let mut visible = Vec::new();
+        for block_x in 0..width {
+            for block_y in 0..height {
+                let block = [block_x, block_y];
+                if !self.blocks.get(block).unwrap().in_view(frustum) {
+                    continue;
+                }
+                visible.extend(block_chunks(block, self.chunks.size()).filter(|&chunk| {
+                    self.chunks.get(chunk).unwrap().in_view(frustum) && !self.occluded(chunk, eye)
+                }));
+            }
+        }
+        visible
+    }
+    //A chunk is hidden when the ground under the lowest point of a chunk in front of it blocks the sight lines
+    //to all four corners of its top, the ground is convex so everything between the corners is hidden too
+    pub fn occluded(&self, chunk: [u32; 2], eye: Vec3) -> bool {
+        let Some(bounds) = self.bounds(chunk) else {
+            return false;
+        };
+        let corners = [
+            Vec3::new(bounds.min.x, bounds.max.y, bounds.min.z),
+            Vec3::new(bounds.max.x, bounds.max.y, bounds.min.z),
+            Vec3::new(bounds.min.x, bounds.max.y, bounds.max.z),
+            bounds.max,
+        ];
+        //Any chunk that blocks every corner also blocks the centre, so only the chunks along its sight line are checked
+        let centre = (bounds.min.xz() + bounds.max.xz()) / 2.0;
+        let distance = eye.xz().distance(centre);
+        let steps = (distance / OCCLUSION_STEP).ceil() as u32;
+        let mut last = None;
+        for step in 0..steps {
+            let position = eye.xz().lerp(centre, step as f32 / steps as f32);
+            if position.x < 0.0 || position.y < 0.0 {
+                continue;
+            }
+            let occluder = [
+                position.x as u32 / CHUNK_SIZE,
+                position.y as u32 / CHUNK_SIZE,
+            ];
+            if occluder == chunk || last == Some(occluder) {
+                continue;
+            }
+            last = Some(occluder);
+            let Some(occluder_bounds) = self.bounds(occluder) else {
+                continue;
+            };
+            if corners
+                .iter()
+                .all(|&corner| occluder_bounds.blocks(eye, corner))
+            {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+fn block_bounds(chunks: &ChunkGrid<ChunkBounds>, block: [u32; 2]) -> ChunkBounds {
+    block_chunks(block, chunks.size())
+        .map(|chunk| *chunks.get(chunk).unwrap())
+        .reduce(ChunkBounds::union)
+        .unwrap()
+}
+
+//The chunks in a block, blocks on the far edges of the map can be smaller
+fn block_chunks(block: [u32; 2], world_size: WorldSize) -> impl Iterator<Item = [u32; 2]> {
+    let start = block.map(|block| block * CULLING_BLOCK_SIZE);
+    let end = [
+        (start[0] + CULLING_BLOCK_SIZE).min(world_size[0]),
+        (start[1] + CULLING_BLOCK_SIZE).min(world_size[1]),
+    ];
+    (start[0]..end[0]).flat_map(move |x| (start[1]..end[1]).map(move |y| [x, y]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A wall of high chunks along x = 1, with low ground on both sides
+    fn walled_heightmap() -> Heightmap {
+        let mut heightmap = Heightmap::new([4, 1]);
+        for x in CHUNK_SIZE..=CHUNK_SIZE * 2 {
+            for y in 0..=CHUNK_SIZE {
+                heightmap[[x, y]] = 0.5;
+            }
+        }
+        heightmap
+    }
+
+    #[test]
+    fn chunks_behind_high_ground_are_occluded() {
+        let culling = ChunkCulling::new(&walled_heightmap(), [4, 1]);
+        let centre = CHUNK_SIZE as f32 / 2.0;
+        let low_eye = Vec3::new(centre, 10.0, centre);
+        assert!(culling.occluded([3, 0], low_eye));
+        //The wall itself and the ground in front of it can be seen
+        assert!(!culling.occluded([1, 0], low_eye));
+        assert!(!culling.occluded([0, 0], low_eye));
+        let high_eye = Vec3::new(centre, WORLD_HEIGHT_SCALE * 4.0, centre);
+        assert!(!culling.occluded([3, 0], high_eye));
+    }
+
+    #[test]
+    fn updated_chunks_match_rebuilt_bounds() {
+        let mut heightmap = Heightmap::new([6, 2]);
+        let mut culling = ChunkCulling::new(&heightmap, [6, 2]);
+        heightmap[[CHUNK_SIZE * 5 + 3, 7]] = 0.75;
+        culling.update_chunks(&heightmap, &[[5, 0]]);
+        let rebuilt = ChunkCulling::new(&heightmap, [6, 2]);
+        assert_eq!(culling.bounds([5, 0]), rebuilt.bounds([5, 0]));
+        assert_eq!(culling.blocks.get([1, 0]), rebuilt.blocks.get([1, 0]));
+        assert!(culling.blocks.get([1, 0]).unwrap().max.y > 0.0);
+    }
+
+    #[test]
+    fn chunks_behind_the_camera_are_culled() {
+        let culling = ChunkCulling::new(&Heightmap::new([16, 16]), [16, 16]);
+        let eye = Vec3::new(8.0, 1.0, 8.0) * CHUNK_SIZE as f32;
+        let view = Transform::from_translation(eye).looking_at(eye + Vec3::X, Vec3::Y);
+        let projection = Mat4::perspective_rh(1.0, 1.0, 0.1, 10000.0);
+        let frustum =
+            Frustum::from_view_projection(&(projection * view.compute_matrix().inverse()));
+        let visible = culling.visible_chunks(&frustum, eye);
+        assert!(visible.contains(&[12, 8]));
+        assert!(!visible.contains(&[3, 8]));
+        assert!(!visible.contains(&[8, 15]));
+    }
+}
@@ -1,13 +1,17 @@
 mod circle_noise;
+pub mod continent_mask;
+pub mod layers;
 
-use itertools::Itertools;
 use noise::*;
 use ordered_float::NotNan;
 use serde::{Deserialize, Serialize};
 
-use self::circle_noise::CircleNoise;
+use self::{
+    continent_mask::{ContinentMask, ContinentShape},
+    layers::build_layers,
+};
 
-use super::consts::CHUNK_WORLD_SIZE;
+use super::WorldSettings;
 
 #[derive(Clone, Copy)]
 pub struct NoiseGenerator<Noise> {
@@ -37,6 +41,9 @@ pub struct NoiseSettings {
     pub mountain_amount: u32,
     pub mountain_size: f64,
     pub hilliness: f64,
+    //Older saves don't have this, so they keep their unshaped coastline
+    #[serde(default)]
+    pub continent_shape: ContinentShape,
 }
 
 impl PartialEq for NoiseSettings {
@@ -45,6 +52,7 @@ impl PartialEq for NoiseSettings {
             && self.mountain_amount == other.mountain_amount
             && NotNan::new(self.mountain_size) == NotNan::new(other.mountain_size)
             && NotNan::new(self.hilliness) == NotNan::new(other.hilliness)
+            && self.continent_shape == other.continent_shape
     }
 }
 impl Eq for NoiseSettings {}
@@ -56,6 +64,7 @@ impl Default for NoiseSettings {
             mountain_amount: 1,
             mountain_size: 100.0,
             hilliness: 0.5,
+            continent_shape: ContinentShape::None,
         }
     }
 }
@@ -64,40 +73,17 @@ pub trait NoiseFunction {
     fn get(&self, index: [u32; 2]) -> f64;
 }
 
-pub fn noise_function(settings: NoiseSettings) -> impl NoiseFunction {
-    let seed = settings.seed;
-    let hilliness = settings.hilliness;
-    let mountain_size = settings.mountain_size;
-
-    let octaves: usize = 4;
-    let sources = (0..octaves)
-        .into_iter()
-        .map(|i| Perlin::new(seed.wrapping_add(i as u32)))
-        .collect_vec();
-
-    let mountain_noise = RidgedMulti::new(seed)
-        .set_octaves(octaves)
-        .set_sources(sources.clone());
-
-    let mountain_noise = ScalePoint::new(mountain_noise).set_scale((1.0 / (mountain_size)) * 0.4);
-
-    let base_terrain_noise = Fbm::new(seed)
-        .set_octaves(octaves)
-        .set_sources(sources.clone());
-    let base_terrain_noise = ScalePoint::new(base_terrain_noise).set_scale(0.001);
-    let base_terrain_noise = ScaleBias::new(base_terrain_noise)
-        .set_scale(hilliness)
-        .set_bias(-0.7);
-
-    let mountain_masks = CircleNoise::new(
-        seed,
-        settings.mountain_amount,
-        settings.mountain_size,
-        CHUNK_WORLD_SIZE,
-        1.0,
+pub fn noise_function(settings: &WorldSettings) -> impl NoiseFunction {
+    let noise = build_layers(
+        settings.noise_settings.seed,
+        &settings.noise_layers(),
+        settings.world_size(),
+    );
+    let noise = ContinentMask::new(
+        noise,
+        settings.noise_settings.continent_shape,
+        settings.world_size(),
+        settings.water_level,
     );
-
-    let noise = Blend::new(base_terrain_noise, mountain_noise, mountain_masks.clone());
-
     NoiseGenerator::new(noise)
 }
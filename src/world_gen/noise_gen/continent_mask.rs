@@ -0,0 +1,158 @@
+use noise::NoiseFn;
+use serde::{Deserialize, Serialize};
+use strum_macros::EnumIter;
+
+use crate::{
+    world::WorldSize,
+    world_gen::{consts::WORLD_HEIGHT_SCALE, HEIGHTMAP_CHUNK_SIZE},
+};
+
+//Fraction of the map the terrain takes to fade into the ocean
+const FALLOFF: f64 = 0.2;
+//Fraction of the map at the ocean edges that is always ocean
+const MARGIN: f64 = 0.02;
+//How far above the sea the edges of landlocked maps are raised, in noise units
+const LANDLOCKED_LIFT: f64 = 0.1;
+
+//Forces the shape of the coastline, north is the top of the heightmap
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, EnumIter)]
+pub enum ContinentShape {
+    #[default]
+    None,
+    //Ocean all around a round landmass
+    Island,
+    //Ocean to the north, east and west, joined to land in the south
+    Peninsula,
+    //Ocean along the north edge
+    Coast,
+    //No ocean at any edge
+    Landlocked,
+}
+
+impl ContinentShape {
+    pub fn name(self) -> &'static str {
+        match self {
+            ContinentShape::None => "None",
+            ContinentShape::Island => "Island",
+            ContinentShape::Peninsula => "Peninsula",
+            ContinentShape::Coast => "Coast",
+            ContinentShape::Landlocked => "Landlocked",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "none" => Some(ContinentShape::None),
+            "island" => Some(ContinentShape::Island),
+            "peninsula" => Some(ContinentShape::Peninsula),
+            "coast" => Some(ContinentShape::Coast),
+            "landlocked" => Some(ContinentShape::Landlocked),
+            _ => None,
+        }
+    }
+    //Distance from the closest edge that should be ocean, as a fraction of the map
+    fn edge_distance(self, [x, y]: [f64; 2]) -> f64 {
+        match self {
+            ContinentShape::None => f64::INFINITY,
+            ContinentShape::Island => 0.5 - ((x - 0.5).powi(2) + (y - 0.5).powi(2)).sqrt(),
+            //The extra margin on the sides narrows the land into a point
+            ContinentShape::Peninsula => (x.min(1.0 - x) - FALLOFF).min(y),
+            ContinentShape::Coast => y,
+            ContinentShape::Landlocked => x.min(1.0 - x).min(y).min(1.0 - y),
+        }
+    }
+}
+
+//Applied on top of the noise layers, 0 at the edges and 1 inland
+pub struct ContinentMask<Source> {
+    source: Source,
+    shape: ContinentShape,
+    map_size: [f64; 2],
+    //The sea level in noise units
+    water_level: f64,
+}
+
+impl<Source> ContinentMask<Source> {
+    pub fn new(
+        source: Source,
+        shape: ContinentShape,
+        world_size: WorldSize,
+        water_level: u32,
+    ) -> Self {
+        Self {
+            source,
+            shape,
+            map_size: [
+                f64::from(world_size[0] * HEIGHTMAP_CHUNK_SIZE),
+                f64::from(world_size[1] * HEIGHTMAP_CHUNK_SIZE),
+            ],
+            water_level: f64::from(water_level) / f64::from(WORLD_HEIGHT_SCALE) * 2.0 - 1.0,
+        }
+    }
+    fn mask(&self, point: [f64; 2]) -> f64 {
+        let point = [point[0] / self.map_size[0], point[1] / self.map_size[1]];
+        let distance = ((self.shape.edge_distance(point) - MARGIN) / FALLOFF).clamp(0.0, 1.0);
+        //Smoothstep, so the coast doesn't have a visible crease
+        distance * distance * (3.0 - 2.0 * distance)
+    }
+}
+
+impl<Source> NoiseFn<f64, 2> for ContinentMask<Source>
+where
+    Source: NoiseFn<f64, 2>,
+{
+    fn get(&self, point: [f64; 2]) -> f64 {
+        let height = self.source.get(point);
+        let edge_height = match self.shape {
+            ContinentShape::None => return height,
+            ContinentShape::Landlocked => {
+                //Only raises the edges, valleys inland are kept
+                let edge_height = self.water_level + LANDLOCKED_LIFT;
+                return height.max(edge_height + (height - edge_height) * self.mask(point));
+            }
+            //The lowest height, so it is under water at any sea level
+            _ => -1.0,
+        };
+        edge_height + (height - edge_height) * self.mask(point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use noise::Constant;
+
+    use super::*;
+
+    const WORLD_SIZE: WorldSize = [2, 2];
+    const WATER_LEVEL: u32 = 10;
+
+    fn mask(shape: ContinentShape, terrain: f64) -> ContinentMask<Constant> {
+        ContinentMask::new(Constant::new(terrain), shape, WORLD_SIZE, WATER_LEVEL)
+    }
+
+    fn centre(mask: &ContinentMask<Constant>) -> [f64; 2] {
+        [mask.map_size[0] / 2.0, mask.map_size[1] / 2.0]
+    }
+
+    #[test]
+    fn ocean_shapes_are_under_water_at_the_north_edge() {
+        for shape in [
+            ContinentShape::Island,
+            ContinentShape::Peninsula,
+            ContinentShape::Coast,
+        ] {
+            let mask = mask(shape, 1.0);
+            let north = [mask.map_size[0] / 2.0, 0.0];
+            assert!(mask.get(north) < mask.water_level, "{shape:?}");
+            assert!((mask.get(centre(&mask)) - 1.0).abs() < 1e-9, "{shape:?}");
+        }
+    }
+
+    #[test]
+    fn landlocked_edges_are_above_water() {
+        let mask = mask(ContinentShape::Landlocked, -1.0);
+        for edge in [[0.0, 0.0], [mask.map_size[0], mask.map_size[1] / 2.0]] {
+            assert!(mask.get(edge) > mask.water_level);
+        }
+        assert!((mask.get(centre(&mask)) + 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,285 @@
+use std::sync::Arc;
+
+use noise::*;
+use ordered_float::NotNan;
+use serde::{Deserialize, Serialize};
+use strum_macros::EnumIter;
+
+use crate::world::WorldSize;
+
+use super::{circle_noise::CircleNoise, NoiseSettings};
+
+pub type BoxedNoise = Box<dyn NoiseFn<f64, 2> + Send + Sync>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, EnumIter)]
+pub enum LayerGenerator {
+    Perlin,
+    Fbm,
+    RidgedMulti,
+    Billow,
+}
+
+impl LayerGenerator {
+    pub fn name(self) -> &'static str {
+        match self {
+            LayerGenerator::Perlin => "Perlin",
+            LayerGenerator::Fbm => "Fbm",
+            LayerGenerator::RidgedMulti => "Ridged",
+            LayerGenerator::Billow => "Billow",
+        }
+    }
+}
+
+//How a layer combines with the layers below it, before the mask is applied
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, EnumIter)]
+pub enum LayerBlend {
+    Replace,
+    Add,
+    Multiply,
+    Max,
+    Min,
+}
+
+impl LayerBlend {
+    pub fn name(self) -> &'static str {
+        match self {
+            LayerBlend::Replace => "Replace",
+            LayerBlend::Add => "Add",
+            LayerBlend::Multiply => "Multiply",
+            LayerBlend::Max => "Max",
+            LayerBlend::Min => "Min",
+        }
+    }
+}
+
+//Where the layer shows through, the layers below are kept wherever the mask is 0
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum LayerMask {
+    None,
+    //Randomly placed circles with a normal falloff, like the default mountains
+    Circles {
+        amount: u32,
+        size: f64,
+        std_dev: f64,
+    },
+}
+
+//NaN can come from presets and layer files, it has to equal itself or the settings would look changed every frame
+impl PartialEq for LayerMask {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (LayerMask::None, LayerMask::None) => true,
+            (
+                LayerMask::Circles {
+                    amount,
+                    size,
+                    std_dev,
+                },
+                LayerMask::Circles {
+                    amount: other_amount,
+                    size: other_size,
+                    std_dev: other_std_dev,
+                },
+            ) => {
+                amount == other_amount
+                    && NotNan::new(*size) == NotNan::new(*other_size)
+                    && NotNan::new(*std_dev) == NotNan::new(*other_std_dev)
+            }
+            _ => false,
+        }
+    }
+}
+impl Eq for LayerMask {}
+
+impl LayerMask {
+    //One of each kind of mask, with the settings used when it's picked in the editor
+    pub const OPTIONS: [LayerMask; 2] = [
+        LayerMask::None,
+        LayerMask::Circles {
+            amount: 3,
+            size: 100.0,
+            std_dev: 1.0,
+        },
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            LayerMask::None => "None",
+            LayerMask::Circles { .. } => "Circles",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NoiseLayer {
+    pub generator: LayerGenerator,
+    //Frequency of the noise, smaller values make bigger features
+    pub scale: f64,
+    pub amplitude: f64,
+    pub bias: f64,
+    pub octaves: usize,
+    //Added to the world seed, so layers with the same generator don't line up
+    pub seed_offset: u32,
+    pub blend: LayerBlend,
+    pub mask: LayerMask,
+}
+
+impl PartialEq for NoiseLayer {
+    fn eq(&self, other: &Self) -> bool {
+        self.generator == other.generator
+            && NotNan::new(self.scale) == NotNan::new(other.scale)
+            && NotNan::new(self.amplitude) == NotNan::new(other.amplitude)
+            && NotNan::new(self.bias) == NotNan::new(other.bias)
+            && self.octaves == other.octaves
+            && self.seed_offset == other.seed_offset
+            && self.blend == other.blend
+            && self.mask == other.mask
+    }
+}
+impl Eq for NoiseLayer {}
+
+impl Default for NoiseLayer {
+    fn default() -> Self {
+        Self {
+            generator: LayerGenerator::Fbm,
+            scale: 0.005,
+            amplitude: 0.5,
+            bias: 0.0,
+            octaves: 4,
+            seed_offset: 0,
+            blend: LayerBlend::Add,
+            mask: LayerMask::None,
+        }
+    }
+}
+
+//Lets the layers below be used both in the blend and in the mask
+struct SharedNoise(Arc<BoxedNoise>);
+
+impl NoiseFn<f64, 2> for SharedNoise {
+    fn get(&self, point: [f64; 2]) -> f64 {
+        self.0.get(point)
+    }
+}
+
+impl NoiseLayer {
+    fn build_generator(&self, seed: u32) -> BoxedNoise {
+        let seed = seed.wrapping_add(self.seed_offset);
+        let octaves = self.octaves.clamp(1, 16);
+        let sources = (0..octaves)
+            .map(|i| Perlin::new(seed.wrapping_add(i as u32)))
+            .collect::<Vec<_>>();
+        let generator: BoxedNoise = match self.generator {
+            LayerGenerator::Perlin => Box::new(Perlin::new(seed)),
+            LayerGenerator::Fbm => Box::new(
+                Fbm::<Perlin>::new(seed)
+                    .set_octaves(octaves)
+                    .set_sources(sources),
+            ),
+            LayerGenerator::RidgedMulti => Box::new(
+                RidgedMulti::<Perlin>::new(seed)
+                    .set_octaves(octaves)
+                    .set_sources(sources),
+            ),
+            LayerGenerator::Billow => Box::new(
+                Billow::<Perlin>::new(seed)
+                    .set_octaves(octaves)
+                    .set_sources(sources),
+            ),
+        };
+        let generator = ScalePoint::new(generator).set_scale(self.scale);
+        Box::new(
+            ScaleBias::new(generator)
+                .set_scale(self.amplitude)
+                .set_bias(self.bias),
+        )
+    }
+
+    fn build_mask(&self, seed: u32, world_size: WorldSize) -> Option<BoxedNoise> {
+        match self.mask {
+            LayerMask::None => None,
+            LayerMask::Circles {
+                amount,
+                size,
+                std_dev,
+            } => Some(Box::new(CircleNoise::new(
+                seed.wrapping_add(self.seed_offset),
+                amount,
+                size,
+                world_size,
+                std_dev,
+            ))),
+        }
+    }
+}
+
+//Stacks the layers from the bottom up, starting from flat ground at 0
+pub fn build_layers(seed: u32, layers: &[NoiseLayer], world_size: WorldSize) -> BoxedNoise {
+    let mut noise: BoxedNoise = Box::new(Constant::new(0.0));
+    for layer in layers {
+        let below = Arc::new(noise);
+        let generator = layer.build_generator(seed);
+        let below_noise = || SharedNoise(below.clone());
+        let combined: BoxedNoise = match layer.blend {
+            LayerBlend::Replace => generator,
+            LayerBlend::Add => Box::new(Add::new(below_noise(), generator)),
+            LayerBlend::Multiply => Box::new(Multiply::new(below_noise(), generator)),
+            LayerBlend::Max => Box::new(Max::new(below_noise(), generator)),
+            LayerBlend::Min => Box::new(Min::new(below_noise(), generator)),
+        };
+        noise = match layer.build_mask(seed, world_size) {
+            Some(mask) => Box::new(Blend::new(below_noise(), combined, mask)),
+            None => combined,
+        };
+    }
+    noise
+}
+
+//The original recipe: hilly fbm terrain, with ridged mountains blended in through circles
+pub fn default_layers(settings: &NoiseSettings) -> Vec<NoiseLayer> {
+    vec![
+        NoiseLayer {
+            generator: LayerGenerator::Fbm,
+            scale: 0.001,
+            amplitude: settings.hilliness,
+            bias: -0.7,
+            octaves: 4,
+            seed_offset: 0,
+            blend: LayerBlend::Replace,
+            mask: LayerMask::None,
+        },
+        NoiseLayer {
+            generator: LayerGenerator::RidgedMulti,
+            scale: (1.0 / (settings.mountain_size)) * 0.4,
+            amplitude: 1.0,
+            bias: 0.0,
+            octaves: 4,
+            seed_offset: 0,
+            blend: LayerBlend::Replace,
+            mask: LayerMask::Circles {
+                amount: settings.mountain_amount,
+                size: settings.mountain_size,
+                std_dev: 1.0,
+            },
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_with_nan_still_equal_themselves() {
+        let layer = NoiseLayer {
+            scale: f64::NAN,
+            mask: LayerMask::Circles {
+                amount: 3,
+                size: f64::NAN,
+                std_dev: 1.0,
+            },
+            ..Default::default()
+        };
+        assert_eq!(layer, layer.clone());
+        assert_ne!(layer, NoiseLayer::default());
+    }
+}
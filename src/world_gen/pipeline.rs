@@ -0,0 +1,271 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+
+use super::{heightmap::Heightmap, WorldSettings};
+
+//The steps of world generation in the order they run, each one starts from the heightmap the last one left
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GenerationStage {
+    #[default]
+    Noise,
+    Erosion,
+    //Blurs or thermally erodes the hydraulic erosion
+    Smoothing,
+    Rivers,
+    Finalize,
+    Done,
+}
+
+impl GenerationStage {
+    pub const ALL: [GenerationStage; 6] = [
+        GenerationStage::Noise,
+        GenerationStage::Erosion,
+        GenerationStage::Smoothing,
+        GenerationStage::Rivers,
+        GenerationStage::Finalize,
+        GenerationStage::Done,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GenerationStage::Noise => "Noise",
+            GenerationStage::Erosion => "Erosion",
+            GenerationStage::Smoothing => "Smoothing",
+            GenerationStage::Rivers => "Rivers",
+            GenerationStage::Finalize => "Finalize",
+            GenerationStage::Done => "Done",
+        }
+    }
+    //Shown on the load bar
+    pub fn description(self) -> &'static str {
+        match self {
+            GenerationStage::Noise => "Generating Heightmap",
+            GenerationStage::Erosion => "Eroding Heightmap",
+            GenerationStage::Smoothing => "Smoothing Heightmap",
+            GenerationStage::Rivers => "Carving Rivers",
+            GenerationStage::Finalize => "Finalizing",
+            GenerationStage::Done => "Done",
+        }
+    }
+    fn index(self) -> usize {
+        self as usize
+    }
+    fn next(self) -> Self {
+        Self::ALL[(self.index() + 1).min(Self::ALL.len() - 1)]
+    }
+}
+
+//What a stage system should do this frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StageStatus {
+    //Another stage is running or generation was cancelled, any work in progress should be dropped
+    Inactive,
+    //The stage has to start over from the current heightmap
+    Started,
+    Running,
+}
+
+#[derive(Resource)]
+pub struct WorldGenPipeline {
+    stage: GenerationStage,
+    //Progress through the current stage, from 0 to 1
+    stage_progress: f32,
+    //Bumped whenever a stage has to start over, so stage systems can tell their work is out of date
+    run: u32,
+    //The settings the current run was started with, stages never read the world settings directly
+    settings: WorldSettings,
+    //The heightmap the current stage started from, so a cancelled stage can be resumed
+    stage_input: Option<Heightmap>,
+    cancelled: bool,
+    started_at: Instant,
+    stage_started_at: Instant,
+}
+
+impl WorldGenPipeline {
+    pub fn new(settings: WorldSettings) -> Self {
+        Self {
+            stage: GenerationStage::Noise,
+            stage_progress: 0.0,
+            run: 0,
+            settings,
+            stage_input: None,
+            cancelled: false,
+            started_at: Instant::now(),
+            stage_started_at: Instant::now(),
+        }
+    }
+    pub fn stage(&self) -> GenerationStage {
+        self.stage
+    }
+    pub fn settings(&self) -> &WorldSettings {
+        &self.settings
+    }
+    pub fn stage_progress(&self) -> f32 {
+        self.stage_progress
+    }
+    //Progress through every stage, each stage counts the same
+    pub fn progress(&self) -> f32 {
+        let stages = GenerationStage::Done.index() as f32;
+        ((self.stage.index() as f32 + self.stage_progress) / stages).min(1.0)
+    }
+    pub fn is_finished(&self) -> bool {
+        self.stage == GenerationStage::Done
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+    //Whether the heightmap is still going to change
+    pub fn is_generating(&self) -> bool {
+        !self.is_finished() && !self.cancelled
+    }
+    //Whether the stage is the one that should be working
+    pub fn running(&self, stage: GenerationStage) -> bool {
+        self.stage == stage && !self.cancelled
+    }
+    //Total time since generation started
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+    //Stage systems keep the last run they worked on, so they know when to start over
+    pub fn status(&self, stage: GenerationStage, last_run: &mut Option<u32>) -> StageStatus {
+        if !self.running(stage) {
+            *last_run = None;
+            StageStatus::Inactive
+        } else if *last_run != Some(self.run) {
+            *last_run = Some(self.run);
+            StageStatus::Started
+        } else {
+            StageStatus::Running
+        }
+    }
+    pub fn set_progress(&mut self, progress: f32) {
+        self.stage_progress = progress.clamp(0.0, 1.0);
+    }
+    //Throws away every stage and starts again from the noise
+    pub fn restart(&mut self, settings: WorldSettings) {
+        *self = Self {
+            run: self.run.wrapping_add(1),
+            ..Self::new(settings)
+        };
+    }
+    //Moves on to the next stage, the heightmap is what the finished stage produced
+    pub fn advance(&mut self, heightmap: &Heightmap) {
+        println!(
+            "{} took: {:?}",
+            self.stage.name(),
+            self.stage_started_at.elapsed()
+        );
+        self.stage = self.stage.next();
+        self.stage_progress = 0.0;
+        self.stage_started_at = Instant::now();
+        self.stage_input = (!self.is_finished()).then(|| heightmap.clone());
+    }
+    //Stops the current stage and puts back the heightmap it started from
+    pub fn cancel(&mut self, heightmap: &mut Heightmap) {
+        if !self.is_generating() {
+            return;
+        }
+        self.cancelled = true;
+        self.stage_progress = 0.0;
+        if let Some(stage_input) = &self.stage_input {
+            *heightmap = stage_input.clone();
+        }
+    }
+    //Starts the cancelled stage over, the earlier stages are kept
+    pub fn resume(&mut self) {
+        if !self.cancelled {
+            return;
+        }
+        self.cancelled = false;
+        self.run = self.run.wrapping_add(1);
+        self.stage_started_at = Instant::now();
+    }
+}
+
+//Nothing is left to compute, but the preview and the hydrology wait for the heightmap to stop changing
+pub fn finalize_generation(
+    mut heightmap: ResMut<Heightmap>,
+    mut pipeline: ResMut<WorldGenPipeline>,
+) {
+    if !pipeline.running(GenerationStage::Finalize) {
+        return;
+    }
+    heightmap.set_changed();
+    pipeline.advance(&heightmap);
+    println!("World generation took: {:?}", pipeline.elapsed());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_restart_when_the_run_changes() {
+        let heightmap = Heightmap::new([1, 1]);
+        let mut pipeline = WorldGenPipeline::new(WorldSettings::default());
+        let mut noise_run = None;
+        let mut erosion_run = None;
+        assert_eq!(
+            pipeline.status(GenerationStage::Noise, &mut noise_run),
+            StageStatus::Started
+        );
+        assert_eq!(
+            pipeline.status(GenerationStage::Noise, &mut noise_run),
+            StageStatus::Running
+        );
+        pipeline.advance(&heightmap);
+        assert_eq!(
+            pipeline.status(GenerationStage::Noise, &mut noise_run),
+            StageStatus::Inactive
+        );
+        assert_eq!(
+            pipeline.status(GenerationStage::Erosion, &mut erosion_run),
+            StageStatus::Started
+        );
+        pipeline.restart(WorldSettings::default());
+        assert_eq!(pipeline.stage(), GenerationStage::Noise);
+        assert_eq!(
+            pipeline.status(GenerationStage::Noise, &mut noise_run),
+            StageStatus::Started
+        );
+    }
+
+    #[test]
+    fn cancelled_stages_resume_from_their_input() {
+        let mut heightmap = Heightmap::new([1, 1]);
+        heightmap[[3, 4]] = 0.5;
+        let mut pipeline = WorldGenPipeline::new(WorldSettings::default());
+        let mut run = None;
+        pipeline.advance(&heightmap);
+        pipeline.status(GenerationStage::Erosion, &mut run);
+        heightmap[[3, 4]] = 0.25;
+        pipeline.cancel(&mut heightmap);
+        assert!(heightmap[[3, 4]] == 0.5);
+        assert!(!pipeline.is_generating());
+        assert_eq!(
+            pipeline.status(GenerationStage::Erosion, &mut run),
+            StageStatus::Inactive
+        );
+        pipeline.resume();
+        assert_eq!(pipeline.stage(), GenerationStage::Erosion);
+        assert_eq!(
+            pipeline.status(GenerationStage::Erosion, &mut run),
+            StageStatus::Started
+        );
+    }
+
+    #[test]
+    fn progress_covers_every_stage() {
+        let heightmap = Heightmap::new([1, 1]);
+        let mut pipeline = WorldGenPipeline::new(WorldSettings::default());
+        assert!(pipeline.progress() == 0.0);
+        while !pipeline.is_finished() {
+            pipeline.set_progress(0.5);
+            let progress = pipeline.progress();
+            pipeline.advance(&heightmap);
+            assert!(pipeline.progress() > progress);
+        }
+        assert!(pipeline.progress() == 1.0);
+    }
+}
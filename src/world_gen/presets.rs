@@ -0,0 +1,273 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::save::save_path;
+
+use super::{
+    erosion::thermal_erosion::ThermalErosionSettings, noise_gen::NoiseSettings, WorldSettings,
+};
+
+//A named set of world settings, stored as <name>.ron in the presets folder
+#[derive(Clone)]
+pub struct WorldPreset {
+    pub name: String,
+    pub settings: WorldSettings,
+}
+
+#[derive(Debug)]
+pub enum PresetError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+    InvalidName(String),
+    NotFound(String),
+}
+
+impl fmt::Display for PresetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PresetError::Io(error) => write!(f, "Couldn't access the preset: {error}"),
+            PresetError::Parse(error) => write!(f, "The preset is invalid: {error}"),
+            PresetError::Serialize(error) => write!(f, "Couldn't serialize the preset: {error}"),
+            PresetError::InvalidName(name) => write!(f, "\"{name}\" is not a valid preset name"),
+            PresetError::NotFound(name) => write!(f, "There is no preset called \"{name}\""),
+        }
+    }
+}
+
+impl std::error::Error for PresetError {}
+
+impl From<io::Error> for PresetError {
+    fn from(error: io::Error) -> Self {
+        PresetError::Io(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for PresetError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        PresetError::Parse(error)
+    }
+}
+
+impl From<ron::Error> for PresetError {
+    fn from(error: ron::Error) -> Self {
+        PresetError::Serialize(error)
+    }
+}
+
+//Next to the saves, so presets can be shared and edited by hand
+pub fn presets_path() -> PathBuf {
+    save_path().join("presets")
+}
+
+//The presets that ship with the game, the seed and map size are left at their defaults
+pub fn builtin_presets() -> Vec<WorldPreset> {
+    let preset = |name: &str,
+                  noise_settings,
+                  erosion_amount,
+                  water_level,
+                  river_amount,
+                  thermal_iterations| WorldPreset {
+        name: name.to_string(),
+        settings: WorldSettings {
+            noise_settings,
+            erosion_amount,
+            water_level,
+            river_amount,
+            thermal_erosion: ThermalErosionSettings {
+                iterations: thermal_iterations,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    };
+    vec![
+        preset(
+            "Flat plains",
+            NoiseSettings {
+                mountain_amount: 0,
+                hilliness: 0.15,
+                ..Default::default()
+            },
+            0,
+            5,
+            4,
+            0,
+        ),
+        preset(
+            "Alpine",
+            NoiseSettings {
+                mountain_amount: 8,
+                mountain_size: 180.0,
+                hilliness: 0.9,
+                ..Default::default()
+            },
+            30,
+            10,
+            6,
+            30,
+        ),
+        //Most of the low ground is flooded, so only the mountains stick out
+        preset(
+            "Archipelago",
+            NoiseSettings {
+                mountain_amount: 10,
+                mountain_size: 60.0,
+                hilliness: 0.7,
+                ..Default::default()
+            },
+            10,
+            90,
+            0,
+            10,
+        ),
+        //Heavy erosion carves valleys between a couple of wide mountains
+        preset(
+            "River valley",
+            NoiseSettings {
+                mountain_amount: 2,
+                mountain_size: 200.0,
+                hilliness: 0.5,
+                ..Default::default()
+            },
+            60,
+            5,
+            16,
+            0,
+        ),
+    ]
+}
+
+//Writes out any built in preset that isn't on disk yet, edited presets are left alone
+pub fn initalize_presets() -> Result<(), PresetError> {
+    fs::create_dir_all(presets_path())?;
+    for preset in builtin_presets() {
+        let path = preset_file(&preset.name)?;
+        if !path.exists() {
+            write_preset(&path, &preset.settings)?;
+        }
+    }
+    Ok(())
+}
+
+//Preset names become file names, so they can't contain a path
+fn preset_file(name: &str) -> Result<PathBuf, PresetError> {
+    //Appended rather than set, so a dot in the name isn't taken for an extension
+    let file_name = PathBuf::from(format!("{name}.ron"));
+    if name.is_empty() || file_name.components().count() != 1 {
+        return Err(PresetError::InvalidName(name.to_string()));
+    }
+    Ok(presets_path().join(file_name))
+}
+
+fn write_preset(path: &Path, settings: &WorldSettings) -> Result<(), PresetError> {
+    let text = ron::ser::to_string_pretty(settings, ron::ser::PrettyConfig::default())?;
+    fs::write(path, text)?;
+    Ok(())
+}
+
+pub fn save_preset(name: &str, settings: &WorldSettings) -> Result<(), PresetError> {
+    fs::create_dir_all(presets_path())?;
+    write_preset(&preset_file(name)?, settings)
+}
+
+//Built in presets can still be loaded if their file was deleted
+pub fn load_preset(name: &str) -> Result<WorldSettings, PresetError> {
+    match fs::read_to_string(preset_file(name)?) {
+        Ok(text) => Ok(ron::from_str(&text)?),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => builtin_presets()
+            .into_iter()
+            .find(|preset| preset.name.eq_ignore_ascii_case(name))
+            .map(|preset| preset.settings)
+            .ok_or(PresetError::NotFound(name.to_string())),
+        Err(error) => Err(error.into()),
+    }
+}
+
+//Presets only change the style of the world, the seed and map size the player picked are kept
+pub fn apply_preset(world_settings: &mut WorldSettings, preset: &WorldSettings) {
+    *world_settings = WorldSettings {
+        noise_settings: NoiseSettings {
+            seed: world_settings.seed(),
+            ..preset.noise_settings
+        },
+        map_size: world_settings.map_size,
+        ..preset.clone()
+    };
+}
+
+//Every preset in the presets folder sorted by name, presets that fail to parse are skipped
+pub fn list_presets() -> Result<Vec<WorldPreset>, PresetError> {
+    let mut presets = Vec::new();
+    for entry in fs::read_dir(presets_path())? {
+        let path = entry?.path();
+        if path.extension().unwrap_or_default() != "ron" {
+            continue;
+        }
+        let name = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        match load_preset(&name) {
+            Ok(settings) => presets.push(WorldPreset { name, settings }),
+            Err(error) => println!("Failed to load preset {}: {error}", path.display()),
+        }
+    }
+    presets.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(presets)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::world_gen::MapSize;
+
+    use super::*;
+
+    #[test]
+    fn builtin_presets_round_trip() {
+        for preset in builtin_presets() {
+            let text =
+                ron::ser::to_string_pretty(&preset.settings, ron::ser::PrettyConfig::default())
+                    .unwrap();
+            let settings: WorldSettings = ron::from_str(&text).unwrap();
+            assert!(settings == preset.settings, "{} changed", preset.name);
+            assert!(preset_file(&preset.name).is_ok());
+        }
+    }
+
+    #[test]
+    fn preset_names_cant_be_paths() {
+        assert!(preset_file("").is_err());
+        assert!(preset_file("../saves/world").is_err());
+        //Dots are part of the name
+        assert!(preset_file("v1.2").unwrap().ends_with("v1.2.ron"));
+        assert_ne!(preset_file("v1.2").unwrap(), preset_file("v1").unwrap());
+    }
+
+    #[test]
+    fn presets_keep_the_seed_and_map_size() {
+        let mut world_settings = WorldSettings {
+            map_size: MapSize::Large,
+            ..Default::default()
+        };
+        world_settings.noise_settings.seed = 1234;
+        let alpine = builtin_presets()
+            .into_iter()
+            .find(|preset| preset.name == "Alpine")
+            .unwrap();
+        apply_preset(&mut world_settings, &alpine.settings);
+        assert_eq!(world_settings.seed(), 1234);
+        assert_eq!(world_settings.map_size, MapSize::Large);
+        assert_eq!(
+            world_settings.noise_settings.mountain_amount,
+            alpine.settings.noise_settings.mountain_amount
+        );
+        assert_eq!(
+            world_settings.erosion_amount,
+            alpine.settings.erosion_amount
+        );
+    }
+}
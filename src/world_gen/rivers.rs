@@ -0,0 +1,320 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{block_on, AsyncComputeTaskPool, Task},
+};
+use itertools::Itertools;
+use ordered_float::OrderedFloat;
+use rand::{
+    distributions::{Distribution, Uniform},
+    rngs::StdRng,
+    SeedableRng,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    consts::WORLD_HEIGHT_SCALE,
+    heightmap::Heightmap,
+    pipeline::{GenerationStage, StageStatus, WorldGenPipeline},
+    WorldSettings,
+};
+
+//Random points checked when picking sources, the highest ones that are far enough apart are used
+const SOURCE_CANDIDATES: usize = 4096;
+//In heightmap points
+const MIN_SOURCE_SPACING: f32 = 64.0;
+const MAX_RIVER_LENGTH: usize = 4096;
+//Pits that would flood more points than this end the river instead of becoming a lake
+const MAX_LAKE_SIZE: usize = 4096;
+//Depth of the channel where the flow is 1, deeper downstream
+const CHANNEL_DEPTH: f32 = 0.5 / WORLD_HEIGHT_SCALE;
+const MAX_CHANNEL_DEPTH: f32 = 4.0 / WORLD_HEIGHT_SCALE;
+const MAX_CHANNEL_RADIUS: u32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RiverPoint {
+    pub position: [u32; 2],
+    //How many points upstream drain through here, including tributaries
+    pub flow: f32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct River {
+    //From the source downstream, the river jumps from the bottom of a lake to where the lake overflows
+    pub points: Vec<RiverPoint>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Lake {
+    //The lowest point of the lake, the rest of it is every connected point at the surface height
+    pub position: [u32; 2],
+    pub surface: f32,
+    //In heightmap points
+    pub area: u32,
+}
+
+#[derive(Resource, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RiverNetwork {
+    pub rivers: Vec<River>,
+    pub lakes: Vec<Lake>,
+}
+
+//Runs once the heightmap is eroded, since erosion would fill the channels back in
+pub fn generate_river_network(
+    mut commands: Commands,
+    mut heightmap: ResMut<Heightmap>,
+    mut pipeline: ResMut<WorldGenPipeline>,
+    mut task: Local<Option<Task<(Heightmap, RiverNetwork)>>>,
+    mut run: Local<Option<u32>>,
+) {
+    match pipeline.status(GenerationStage::Rivers, &mut run) {
+        StageStatus::Inactive => {
+            //Dropping the task cancels it
+            *task = None;
+            return;
+        }
+        StageStatus::Started => {
+            let mut heightmap = heightmap.clone();
+            let settings = pipeline.settings().clone();
+            *task = Some(AsyncComputeTaskPool::get().spawn(async move {
+                let network = generate_rivers(&mut heightmap, &settings);
+                (heightmap, network)
+            }));
+        }
+        StageStatus::Running => {}
+    }
+    if task.as_ref().is_some_and(Task::is_finished) {
+        let (carved, network) = block_on(task.take().unwrap());
+        *heightmap = carved;
+        commands.insert_resource(network);
+        pipeline.advance(&heightmap);
+    }
+}
+
+//Traces rivers downhill from the highest points until they reach the sea or the edge of the map, then carves them
+pub fn generate_rivers(heightmap: &mut Heightmap, settings: &WorldSettings) -> RiverNetwork {
+    let mut network = RiverNetwork::default();
+    let sea_level = settings.water_level as f32 / WORLD_HEIGHT_SCALE;
+    //The river and point index of every traced point, so tributaries know where they join
+    let mut traced: HashMap<[u32; 2], (usize, usize)> = HashMap::new();
+    //Where each river flows into another one
+    let mut joins: Vec<Option<(usize, usize)>> = Vec::new();
+
+    for source in pick_sources(heightmap, settings, sea_level) {
+        let river_index = network.rivers.len();
+        let mut river = River::default();
+        let mut joined = None;
+        let mut position = source;
+        while river.points.len() < MAX_RIVER_LENGTH {
+            if let Some(&(other, index)) = traced.get(&position) {
+                //A lake can overflow back into the same river
+                if other != river_index {
+                    joined = Some((other, index));
+                }
+                break;
+            }
+            traced.insert(position, (river_index, river.points.len()));
+            river.points.push(RiverPoint {
+                position,
+                flow: (river.points.len() + 1) as f32,
+            });
+            if heightmap[position] < sea_level || on_edge(heightmap, position) {
+                break;
+            }
+            position = match lowest_neighbour(heightmap, position) {
+                Some(next) => next,
+                None => match fill_lake(heightmap, position) {
+                    Some((lake, outlet)) => {
+                        network.lakes.push(lake);
+                        outlet
+                    }
+                    None => break,
+                },
+            };
+        }
+        if river.points.is_empty() {
+            continue;
+        }
+
+        //The water from this river flows through every river downstream of it
+        let flow = river.points.len() as f32;
+        let mut downstream = joined;
+        while let Some((other, index)) = downstream {
+            for point in &mut network.rivers[other].points[index..] {
+                point.flow += flow;
+            }
+            downstream = joins[other];
+        }
+        network.rivers.push(river);
+        joins.push(joined);
+    }
+
+    carve_rivers(heightmap, &network);
+    network
+}
+
+fn pick_sources(heightmap: &Heightmap, settings: &WorldSettings, sea_level: f32) -> Vec<[u32; 2]> {
+    let mut rng = StdRng::seed_from_u64(u64::from(settings.seed()));
+    let [width, height] = heightmap.size();
+    let x_sampler = Uniform::new(0, width);
+    let y_sampler = Uniform::new(0, height);
+    let candidates = (0..SOURCE_CANDIDATES)
+        .map(|_| [x_sampler.sample(&mut rng), y_sampler.sample(&mut rng)])
+        .filter(|&point| heightmap[point] > sea_level && !on_edge(heightmap, point))
+        .sorted_by(|a, b| heightmap[*b].total_cmp(&heightmap[*a]));
+
+    let mut sources: Vec<[u32; 2]> = Vec::new();
+    for candidate in candidates {
+        if sources.len() >= settings.river_amount as usize {
+            break;
+        }
+        if sources
+            .iter()
+            .all(|&source| distance(source, candidate) >= MIN_SOURCE_SPACING)
+        {
+            sources.push(candidate);
+        }
+    }
+    sources
+}
+
+fn distance(a: [u32; 2], b: [u32; 2]) -> f32 {
+    let dx = a[0] as f32 - b[0] as f32;
+    let dy = a[1] as f32 - b[1] as f32;
+    (dx * dx + dy * dy).sqrt()
+}
+
+//Rivers flow off the map here, neighbours never include the first row and column
+fn on_edge(heightmap: &Heightmap, [x, y]: [u32; 2]) -> bool {
+    let [width, height] = heightmap.size();
+    x <= 1 || y <= 1 || x + 1 >= width || y + 1 >= height
+}
+
+fn lowest_neighbour(heightmap: &Heightmap, point: [u32; 2]) -> Option<[u32; 2]> {
+    heightmap
+        .neighbours(point)
+        .filter(|&neighbour| heightmap[neighbour] < heightmap[point])
+        .min_by(|a, b| heightmap[*a].total_cmp(&heightmap[*b]))
+}
+
+//Floods the pit from its lowest point until the water finds a way out, returning the lake and where it overflows
+//The heightmap is only changed if the lake isn't too big
+fn fill_lake(heightmap: &mut Heightmap, pit: [u32; 2]) -> Option<(Lake, [u32; 2])> {
+    let mut queue = BinaryHeap::from([Reverse((OrderedFloat(heightmap[pit]), pit))]);
+    let mut seen = HashSet::from([pit]);
+    let mut flooded = Vec::new();
+    let mut surface = heightmap[pit];
+    while let Some(Reverse((OrderedFloat(height), point))) = queue.pop() {
+        //The lowest point around the lake is below the water, so it spills over here
+        if height < surface {
+            for &flooded_point in &flooded {
+                heightmap[flooded_point] = surface;
+            }
+            let lake = Lake {
+                position: pit,
+                surface,
+                area: flooded.len() as u32,
+            };
+            return Some((lake, point));
+        }
+        surface = height;
+        flooded.push(point);
+        if flooded.len() > MAX_LAKE_SIZE || on_edge(heightmap, point) {
+            return None;
+        }
+        for neighbour in heightmap.neighbours(point) {
+            if seen.insert(neighbour) {
+                queue.push(Reverse((OrderedFloat(heightmap[neighbour]), neighbour)));
+            }
+        }
+    }
+    None
+}
+
+fn carve_rivers(heightmap: &mut Heightmap, network: &RiverNetwork) {
+    for river in &network.rivers {
+        //The bed never rises downstream, so carving doesn't leave pits along the river
+        let mut bed = f32::MAX;
+        for point in &river.points {
+            let depth = (CHANNEL_DEPTH * point.flow.sqrt()).min(MAX_CHANNEL_DEPTH);
+            bed = bed.min(heightmap[point.position] - depth).max(0.0);
+            let radius = ((point.flow.sqrt() / 8.0) as u32).min(MAX_CHANNEL_RADIUS);
+            heightmap[point.position] = heightmap[point.position].min(bed);
+            //The banks slope up from the bed to the edge of the channel
+            for bank in heightmap.get_circle(point.position, radius) {
+                let slope = distance(bank, point.position) / (radius + 1) as f32;
+                heightmap[bank] = heightmap[bank].min(bed + depth * slope);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::world_gen::MapSize;
+
+    use super::*;
+
+    fn settings(river_amount: u32) -> WorldSettings {
+        WorldSettings {
+            map_size: MapSize::Custom([1, 1]),
+            water_level: 15,
+            river_amount,
+            ..Default::default()
+        }
+    }
+
+    //Slopes down towards x = 0, where it is under the sea
+    fn slope() -> Heightmap {
+        let mut heightmap = Heightmap::new([1, 1]);
+        let [width, height] = heightmap.size();
+        for x in 0..width {
+            for y in 0..height {
+                heightmap[[x, y]] = x as f32 / width as f32 * 0.5;
+            }
+        }
+        heightmap
+    }
+
+    #[test]
+    fn rivers_flow_downhill_to_the_sea() {
+        let mut heightmap = slope();
+        let original = heightmap.clone();
+        let network = generate_rivers(&mut heightmap, &settings(2));
+        assert_eq!(network.rivers.len(), 2);
+        for river in &network.rivers {
+            let heights = river
+                .points
+                .iter()
+                .map(|point| original[point.position])
+                .collect_vec();
+            assert!(heights.windows(2).all(|pair| pair[1] < pair[0]));
+            let last = river.points.last().unwrap();
+            assert!(last.flow >= river.points.len() as f32);
+            assert!(heightmap[last.position] <= original[last.position]);
+        }
+    }
+
+    #[test]
+    fn pits_become_lakes() {
+        let mut heightmap = slope();
+        let pit = [64, 64];
+        heightmap[pit] = 0.0;
+        let (lake, outlet) = fill_lake(&mut heightmap, pit).unwrap();
+        assert_eq!(lake.position, pit);
+        assert!(heightmap[pit] == lake.surface);
+        assert!(heightmap[outlet] < lake.surface);
+    }
+
+    #[test]
+    fn no_rivers_leaves_the_heightmap_alone() {
+        let mut heightmap = slope();
+        let network = generate_rivers(&mut heightmap, &settings(0));
+        assert_eq!(network, RiverNetwork::default());
+        assert_eq!(heightmap.data, slope().data);
+    }
+}
@@ -1,24 +1,22 @@
 use bevy::{
-    pbr::MaterialExtension,
+    pbr::{ExtendedMaterial, MaterialExtension},
     prelude::*,
     reflect::TypePath,
     render::render_resource::{AsBindGroup, ShaderRef},
 };
 
+//The terrain atlas as the base colour texture, with the terrain type of each vertex in the vertex colour
+pub type ExtendedTerrainMaterial = ExtendedMaterial<StandardMaterial, TerrainMaterial>;
+
 #[derive(Asset, AsBindGroup, TypePath, Debug, Clone)]
 pub struct TerrainMaterial {
-    /* #[uniform(100)]
-    pub size: [u32; 2], */
-    #[texture(100)]
-    #[sampler(101)]
-    pub heightmap: Handle<Image>,
+    //How many rows the atlas has, one for each terrain type
+    #[uniform(100)]
+    pub terrain_types: u32,
 }
 
 impl MaterialExtension for TerrainMaterial {
-    fn vertex_shader() -> ShaderRef {
+    fn fragment_shader() -> ShaderRef {
         "shaders/terrain_material.wgsl".into()
     }
-    /* fn fragment_shader() -> ShaderRef {
-        "shaders/terrain_material.wgsl".into()
-    } */
 }